@@ -1,6 +1,13 @@
-use std::{collections::HashMap, path::Path, time::Duration};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use serde::Deserialize;
+use chrono::Weekday;
+use chrono_tz::Tz;
+use serde::{de, Deserialize, Deserializer};
 use tokio::fs;
 
 use crate::{error::Error, Result};
@@ -9,15 +16,60 @@ use crate::{error::Error, Result};
 pub struct Config {
     pub inputs: HashMap<String, InputConfig>,
     pub outputs: HashMap<String, Vec<OutputConfig>>,
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    pub dedupe: Option<DedupeConfig>,
+
+    /// interval of the inputs without one
+    #[serde(default, with = "humantime_serde")]
+    pub default_interval: Option<Duration>,
+
+    /// where the pending digests are kept
+    #[serde(default = "default_state_dir")]
+    pub state_dir: PathBuf,
+
+    /// receives the entries pushed by websub hubs
+    pub websub: Option<WebSubConfig>,
+}
+
+fn default_state_dir() -> PathBuf {
+    PathBuf::from(".")
+}
+
+/// Whether the id has the `key[position]` form of the ids given to outputs
+/// without one.
+fn is_position_id(id: &str) -> bool {
+    id.strip_suffix(']')
+        .and_then(|id| id.rsplit_once('['))
+        .is_some_and(|(_, i)| !i.is_empty() && i.chars().all(|c| c.is_ascii_digit()))
 }
 
 impl Config {
     pub async fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let file = fs::read(path).await?;
-        let config: Config = toml::from_slice(&file[..])?;
+        let mut config: Config = toml::from_slice(&file[..])?;
+
+        // every output is instantiated once, outputs without an id are told
+        // apart by their position
+        for (key, output_configs) in config.outputs.iter_mut() {
+            for (i, output_config) in output_configs.iter_mut().enumerate() {
+                if output_config.id.is_empty() {
+                    output_config.id = format!("{}[{}]", key, i);
+                } else if is_position_id(&output_config.id) {
+                    return Err(Error::Config(format!(
+                        "outputs: ids ending with [number] are reserved: {}",
+                        output_config.id
+                    )));
+                }
+            }
+        }
 
         config.is_valid()?;
 
+        for input_config in config.inputs.values_mut() {
+            input_config.interval = input_config.interval.or(config.default_interval);
+        }
+
         Ok(config)
     }
 
@@ -40,22 +92,260 @@ impl Config {
             }
         }
 
+        let mut ids: Vec<&String> = Vec::new();
+
+        for output_config in self.outputs.values().flatten() {
+            if ids.contains(&&output_config.id) {
+                return Err(Error::Config(format!(
+                    "outputs: ids are not unique: {}",
+                    output_config.id
+                )));
+            }
+            ids.push(&output_config.id);
+        }
+
+        for route in self.routes.iter() {
+            for output in route.outputs.iter() {
+                if !self.outputs.contains_key(output) && !ids.contains(&output) {
+                    return Err(Error::Config(format!("routes: unknown output: {}", output)));
+                }
+            }
+        }
+
         Ok(())
     }
+
+    /// Output configs referenced by a route, by their key or id.
+    pub fn route_outputs(&self, route: &RouteConfig) -> Vec<&OutputConfig> {
+        route
+            .outputs
+            .iter()
+            .flat_map(|output| match self.outputs.get(output) {
+                Some(output_configs) => output_configs.iter().collect(),
+                None => self
+                    .outputs
+                    .values()
+                    .flatten()
+                    .filter(|output_config| &output_config.id == output)
+                    .collect::<Vec<&OutputConfig>>(),
+            })
+            .collect()
+    }
+
+    /// Output configs forwarded the entries of an input, by the input's name,
+    /// its tags, glob patterns matching either or the `all` catch-all.
+    pub fn outputs_for(&self, name: &str, tags: &[String]) -> Vec<&OutputConfig> {
+        let mut keys: Vec<&String> = self
+            .outputs
+            .keys()
+            .filter(|key| {
+                key.as_str() == "all"
+                    || glob_match(key, name)
+                    || tags.iter().any(|tag| glob_match(key, tag))
+            })
+            .collect();
+
+        // the name and the tags in their order first, then the patterns
+        let rank = |key: &String| {
+            if key == name {
+                0
+            } else {
+                tags.iter()
+                    .position(|tag| tag == key)
+                    .map_or(usize::MAX, |i| i + 1)
+            }
+        };
+        keys.sort_by(|a, b| rank(a).cmp(&rank(b)).then(a.cmp(b)));
+
+        keys.into_iter()
+            .flat_map(|key| self.outputs[key].iter())
+            .filter(|output_config| !output_config.excludes(name, tags))
+            .collect()
+    }
+}
+
+/// Matches `text` against a pattern where `*` stands for any characters and
+/// `?` for a single one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and of the text it was matched at
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // let the last `*` swallow one more character
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
 #[derive(Deserialize)]
 pub struct InputConfig {
-    pub url: String,
+    #[serde(flatten, deserialize_with = "deserialize_input_kind")]
+    pub kind: InputKind,
 
     #[serde(default, with = "humantime_serde")]
     pub interval: Option<Duration>,
 
+    /// polls on a cron schedule instead of every `interval`
+    pub cron: Option<String>,
+
+    /// timezone of `cron`
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+
+    /// derives the interval from the hints of the feed
+    pub adaptive: Option<AdaptiveConfig>,
+
+    /// upper bound of a random delay of the first poll
+    #[serde(default, with = "humantime_serde")]
+    pub startup_offset: Option<Duration>,
+
+    /// upper bound of a random delay of every poll
+    #[serde(default, with = "humantime_serde")]
+    pub jitter: Option<Duration>,
+
     #[serde(default = "default_retry_limit")]
     pub retry_limit: usize,
 
+    /// entries published longer ago are dropped
+    #[serde(default, with = "humantime_serde")]
+    pub max_age: Option<Duration>,
+
+    /// entries published further ahead are dated at the time they're seen
+    #[serde(default, with = "humantime_serde")]
+    pub future_tolerance: Option<Duration>,
+
+    pub max_entries_per_poll: Option<usize>,
+
+    /// what happens to the entries over `max_entries_per_poll`
+    #[serde(default)]
+    pub overflow: Overflow,
+
     #[serde(default = "default_tags")]
     pub tags: Vec<String>,
+
+    #[serde(default)]
+    pub description: DescriptionConfig,
+
+    #[serde(default)]
+    pub filters: Vec<FilterConfig>,
+
+    /// subscribes to the websub hub of the feed when `websub` is set
+    #[serde(default = "default_true")]
+    pub websub: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum InputKind {
+    Rss {
+        url: String,
+    },
+    File {
+        path: PathBuf,
+        /// reads the feed on changes instead of polling it
+        #[serde(default)]
+        watch: bool,
+    },
+    Command {
+        command: String,
+        #[serde(default)]
+        arguments: Vec<String>,
+        #[serde(default)]
+        env: HashMap<String, String>,
+        #[serde(default = "default_command_timeout", with = "humantime_serde")]
+        timeout: Duration,
+        /// what the command prints
+        #[serde(default)]
+        format: CommandFormat,
+    },
+}
+
+const fn default_command_timeout() -> Duration {
+    Duration::from_secs(60)
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandFormat {
+    #[default]
+    Feed,
+    JsonLines,
+}
+
+/// Inputs without a `type` are rss feeds.
+fn deserialize_input_kind<'de, D>(deserializer: D) -> std::result::Result<InputKind, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut table = toml::value::Table::deserialize(deserializer)?;
+    table
+        .entry("type")
+        .or_insert_with(|| toml::Value::String("rss".to_owned()));
+
+    InputKind::deserialize(toml::Value::Table(table)).map_err(de::Error::custom)
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct AdaptiveConfig {
+    #[serde(default = "default_min_interval", with = "humantime_serde")]
+    pub min_interval: Duration,
+    #[serde(default = "default_max_interval", with = "humantime_serde")]
+    pub max_interval: Duration,
+}
+
+const fn default_min_interval() -> Duration {
+    Duration::from_secs(60 * 5)
+}
+
+const fn default_max_interval() -> Duration {
+    Duration::from_secs(60 * 60 * 24)
+}
+
+#[derive(Deserialize)]
+pub struct WebSubConfig {
+    /// address the callback server listens on
+    pub listen: SocketAddr,
+    /// public url of the callback server
+    pub callback_url: String,
+    #[serde(default = "default_lease", with = "humantime_serde")]
+    pub lease: Duration,
+}
+
+const fn default_lease() -> Duration {
+    Duration::from_secs(60 * 60 * 24 * 10)
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Overflow {
+    #[default]
+    Drop,
+    /// pushed by the next polls
+    Defer,
+    /// replaced by a single "and N more" entry
+    Summary,
 }
 
 const fn default_retry_limit() -> usize {
@@ -66,9 +356,258 @@ fn default_tags() -> Vec<String> {
     vec!["default".to_owned()]
 }
 
+/// Skips the entries already pushed to an output by another input.
+#[derive(Clone, Deserialize)]
+pub struct DedupeConfig {
+    #[serde(default = "default_dedupe_window", with = "humantime_serde")]
+    pub window: Duration,
+    #[serde(default = "default_dedupe_keys")]
+    pub by: Vec<DedupeKey>,
+}
+
+const fn default_dedupe_window() -> Duration {
+    Duration::from_secs(60 * 60 * 24)
+}
+
+fn default_dedupe_keys() -> Vec<DedupeKey> {
+    vec![DedupeKey::Url, DedupeKey::Title]
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupeKey {
+    /// the url without tracking parameters
+    Url,
+    /// the title without case and punctuation
+    Title,
+}
+
+/// Pushes the entries of an output together once per period.
+#[derive(Clone, Deserialize)]
+pub struct DigestConfig {
+    #[serde(default, with = "humantime_serde")]
+    pub every: Option<Duration>,
+    /// daily, as HH:MM
+    pub at: Option<String>,
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+}
+
+/// At most `messages` pushes `per` period, 0 disables the limit.
+#[derive(Clone, Copy, Deserialize)]
+pub struct RateLimitConfig {
+    pub messages: u32,
+    #[serde(with = "humantime_serde")]
+    pub per: Duration,
+}
+
+/// Delivery windows of an output.
+#[derive(Clone, Deserialize)]
+pub struct ScheduleConfig {
+    pub windows: Vec<WindowConfig>,
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+    #[serde(default)]
+    pub outside: OutsideWindow,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct WindowConfig {
+    #[serde(default = "default_days")]
+    pub days: Vec<Weekday>,
+    /// HH:MM
+    #[serde(default = "default_midnight")]
+    pub from: String,
+    /// HH:MM, on the next day if it isn't after `from`
+    #[serde(default = "default_midnight")]
+    pub to: String,
+}
+
+fn default_days() -> Vec<Weekday> {
+    vec![
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+        Weekday::Sat,
+        Weekday::Sun,
+    ]
+}
+
+fn default_midnight() -> String {
+    "00:00".to_owned()
+}
+
+/// What happens to the entries pushed outside of the delivery windows.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutsideWindow {
+    /// pushed when a window opens
+    #[default]
+    Hold,
+    Drop,
+}
+
+/// Sends the entries matching `condition` to more outputs.
+#[derive(Deserialize)]
+pub struct RouteConfig {
+    #[serde(rename = "if")]
+    pub condition: String,
+    /// keys or ids of `outputs`
+    pub outputs: Vec<String>,
+    /// matching entries skip the outputs of the input's name and tags
+    #[serde(default)]
+    pub exclusive: bool,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct OutputConfig {
+    /// references the output from routes, generated if unset
+    #[serde(default)]
+    pub id: String,
+
+    #[serde(flatten)]
+    pub kind: OutputKind,
+
+    pub template: Option<TemplateConfig>,
+
+    /// overrides the description settings of the inputs
+    pub description: Option<DescriptionConfig>,
+
+    /// markup of the description, defaults to the one the output renders
+    pub markup: Option<Markup>,
+
+    /// applied after the filters of the input
+    #[serde(default)]
+    pub filters: Vec<FilterConfig>,
+
+    /// glob patterns of input names and tags never forwarded to this output
+    #[serde(default)]
+    pub except: Vec<String>,
+
+    /// opts out of the global `dedupe`
+    #[serde(default = "default_true")]
+    pub dedupe: bool,
+
+    pub digest: Option<DigestConfig>,
+
+    pub schedule: Option<ScheduleConfig>,
+
+    /// overrides the default limit of the output type
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+impl OutputConfig {
+    pub fn excludes(&self, name: &str, tags: &[String]) -> bool {
+        self.except.iter().any(|pattern| {
+            glob_match(pattern, name) || tags.iter().any(|tag| glob_match(pattern, tag))
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Markup {
+    Plain,
+    /// CommonMark as rendered by discord
+    Markdown,
+    /// slack's mrkdwn
+    Slack,
+    /// the html subset supported by telegram
+    TelegramHtml,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+pub struct DescriptionConfig {
+    /// 0 disables truncation
+    pub max_length: Option<usize>,
+    pub truncate: Option<TruncateMode>,
+    /// 0 disables wrapping
+    pub wrap_width: Option<usize>,
+    pub source: Option<DescriptionSource>,
+    pub link_footnotes: Option<bool>,
+}
+
+impl DescriptionConfig {
+    /// Fills the unset settings from `other`.
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            max_length: self.max_length.or(other.max_length),
+            truncate: self.truncate.or(other.truncate),
+            wrap_width: self.wrap_width.or(other.wrap_width),
+            source: self.source.or(other.source),
+            link_footnotes: self.link_footnotes.or(other.link_footnotes),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncateMode {
+    #[default]
+    Char,
+    Word,
+    Sentence,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DescriptionSource {
+    /// summary, falls back to the content
+    #[default]
+    Summary,
+    /// content, falls back to the summary
+    Content,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct FilterConfig {
+    #[serde(default)]
+    pub action: FilterAction,
+    #[serde(default = "default_filter_fields")]
+    pub fields: Vec<FilterField>,
+    pub regex: Option<String>,
+    /// matched as plain substrings
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// replaces `fields`, `regex` and `keywords`
+    pub expression: Option<String>,
+}
+
+fn default_filter_fields() -> Vec<FilterField> {
+    vec![
+        FilterField::Title,
+        FilterField::Description,
+        FilterField::Author,
+        FilterField::Categories,
+        FilterField::Url,
+    ]
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    #[default]
+    Include,
+    Exclude,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterField {
+    Title,
+    Description,
+    Author,
+    Categories,
+    Url,
+}
+
 #[derive(Clone, Deserialize)]
 #[serde(rename_all = "snake_case", tag = "type")]
-pub enum OutputConfig {
+pub enum OutputKind {
     Custom {
         command: String,
         #[serde(default)]
@@ -80,10 +619,115 @@ pub enum OutputConfig {
         token: String,
         user_id: u64,
     },
-    DiscordWebhook {
-        url: String,
-    },
-    Slack {
-        url: String,
-    },
+    DiscordWebhook(DiscordWebhookConfig),
+    Slack(SlackConfig),
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TemplateConfig {
+    pub title: Option<TemplateSource>,
+    pub body: Option<TemplateSource>,
+    #[serde(default)]
+    pub fields: Vec<FieldTemplate>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct FieldTemplate {
+    pub name: String,
+    pub value: TemplateSource,
+    #[serde(default)]
+    pub inline: bool,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum TemplateSource {
+    Inline(String),
+    File { file: PathBuf },
+}
+
+impl TemplateSource {
+    pub async fn load(self) -> Result<String> {
+        match self {
+            TemplateSource::Inline(template) => Ok(template),
+            TemplateSource::File { file } => Ok(fs::read_to_string(file).await?),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct DiscordWebhookConfig {
+    pub url: String,
+
+    pub username: Option<String>,
+    pub avatar_url: Option<String>,
+    #[serde(default)]
+    pub use_feed_icon: bool,
+
+    pub thread_id: Option<u64>,
+    pub thread_name: Option<String>,
+
+    pub content: Option<String>,
+    #[serde(default)]
+    pub role_mentions: Vec<u64>,
+
+    pub color: Option<u32>,
+    #[serde(default)]
+    pub colors: HashMap<String, u32>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct SlackConfig {
+    pub url: Option<String>,
+
+    pub token: Option<String>,
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub thread_per_input: bool,
+
+    pub unfurl_links: Option<bool>,
+    pub unfurl_media: Option<bool>,
+
+    #[serde(default)]
+    pub layout: SlackLayout,
+    #[serde(default = "default_timestamp_format")]
+    pub timestamp_format: String,
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+    #[serde(default = "default_button_text")]
+    pub button_text: String,
+    #[serde(default = "default_true")]
+    pub show_url: bool,
+    #[serde(default)]
+    pub show_input_name: bool,
+    #[serde(default)]
+    pub show_feed_image: bool,
+    #[serde(default = "default_true")]
+    pub show_images: bool,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlackLayout {
+    /// header, description with a button, context and a divider
+    #[default]
+    Full,
+    /// linked title and context
+    Compact,
+}
+
+fn default_timestamp_format() -> String {
+    "%d %b %Y %I:%M %p %Z".to_owned()
+}
+
+const fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
+fn default_button_text() -> String {
+    ":link: Open".to_owned()
+}
+
+const fn default_true() -> bool {
+    true
 }
@@ -14,4 +14,22 @@ pub enum Error {
     Toml(#[from] toml::de::Error),
     #[error("serenity error: {0}")]
     Serenity(#[from] serenity::Error),
+    #[error("slack api error: {0}")]
+    Slack(String),
+    #[error("template error: {0}")]
+    Template(Box<handlebars::TemplateError>),
+    #[error("template render error: {0}")]
+    Render(#[from] handlebars::RenderError),
+    #[error("server error: {0}")]
+    Server(#[from] hyper::Error),
+    #[error("command error: {0}")]
+    Command(String),
+    #[error("watch error: {0}")]
+    Watch(#[from] notify::Error),
+}
+
+impl From<handlebars::TemplateError> for Error {
+    fn from(err: handlebars::TemplateError) -> Self {
+        Error::Template(Box::new(err))
+    }
 }
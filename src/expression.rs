@@ -0,0 +1,266 @@
+use regex::Regex;
+
+use crate::{error::Error, outputs::Entry, Result};
+
+/// Condition over the fields of an entry, e.g.
+/// `title =~ "CVE" and (category == "security" or not tag == "noisy")`.
+pub struct Expression(Node);
+
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Compare(Field, Operator),
+}
+
+#[derive(Clone, Copy)]
+enum Field {
+    Id,
+    Title,
+    Description,
+    Author,
+    Url,
+    FeedTitle,
+    Category,
+    Tag,
+    Input,
+}
+
+enum Operator {
+    Equals(String),
+    NotEquals(String),
+    Contains(String),
+    Matches(Regex),
+    NotMatches(Regex),
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self> {
+        let tokens = tokenize(source).map_err(|e| parse_error(source, e))?;
+
+        let mut parser = Parser { tokens, pos: 0 };
+        let node = parser.or().map_err(|e| parse_error(source, e))?;
+
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(parse_error(source, format!("unexpected {:?}", token)));
+        }
+
+        Ok(Self(node))
+    }
+
+    /// Evaluates the expression for an entry of the input `input`.
+    pub fn matches(&self, input: &str, entry: &Entry) -> bool {
+        self.0.eval(input, entry)
+    }
+}
+
+fn parse_error(source: &str, message: String) -> Error {
+    Error::Config(format!("expression \"{}\": {}", source, message))
+}
+
+impl Node {
+    fn eval(&self, input: &str, entry: &Entry) -> bool {
+        match self {
+            Node::And(left, right) => left.eval(input, entry) && right.eval(input, entry),
+            Node::Or(left, right) => left.eval(input, entry) || right.eval(input, entry),
+            Node::Not(node) => !node.eval(input, entry),
+            Node::Compare(field, operator) => {
+                let values = field.values(input, entry);
+                // list fields match if any of their values does
+                let any = |f: &dyn Fn(&str) -> bool| values.iter().any(|v| f(v));
+
+                match operator {
+                    Operator::Equals(s) => any(&|v| v == s),
+                    Operator::NotEquals(s) => !any(&|v| v == s),
+                    Operator::Contains(s) => any(&|v| v.contains(s.as_str())),
+                    Operator::Matches(regex) => any(&|v| regex.is_match(v)),
+                    Operator::NotMatches(regex) => !any(&|v| regex.is_match(v)),
+                }
+            }
+        }
+    }
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "id" => Field::Id,
+            "title" => Field::Title,
+            "description" => Field::Description,
+            "author" => Field::Author,
+            "url" => Field::Url,
+            "feed_title" => Field::FeedTitle,
+            "category" | "categories" => Field::Category,
+            "tag" | "tags" => Field::Tag,
+            "input" => Field::Input,
+            _ => return None,
+        })
+    }
+
+    fn values<'a>(self, input: &'a str, entry: &'a Entry) -> Vec<&'a str> {
+        match self {
+            Field::Id => vec![&entry.id],
+            Field::Title => vec![&entry.title],
+            Field::Description => vec![&entry.description],
+            Field::Author => entry.author.as_deref().into_iter().collect(),
+            Field::Url => vec![&entry.url],
+            Field::FeedTitle => entry.feed_title.as_deref().into_iter().collect(),
+            Field::Category => entry.categories.iter().map(String::as_str).collect(),
+            Field::Tag => entry.tags.iter().map(String::as_str).collect(),
+            Field::Input => vec![input],
+        }
+    }
+}
+
+fn tokenize(source: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => s.push(c),
+                            None => return Err("unterminated string".to_owned()),
+                        },
+                        Some(c) => s.push(c),
+                        None => return Err("unterminated string".to_owned()),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            '=' | '!' => {
+                chars.next();
+                let op = match (c, chars.next()) {
+                    ('=', Some('=')) => "==",
+                    ('=', Some('~')) => "=~",
+                    ('!', Some('=')) => "!=",
+                    ('!', Some('~')) => "!~",
+                    _ => return Err(format!("unknown operator starting with '{}'", c)),
+                };
+                tokens.push(Token::Op(op));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+                    ident.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            c => return Err(format!("unexpected character '{}'", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.tokens.get(self.pos), Some(Token::Ident(i)) if i == keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> std::result::Result<Node, String> {
+        let mut node = self.and()?;
+        while self.keyword("or") {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> std::result::Result<Node, String> {
+        let mut node = self.unary()?;
+        while self.keyword("and") {
+            node = Node::And(Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> std::result::Result<Node, String> {
+        if self.keyword("not") {
+            return Ok(Node::Not(Box::new(self.unary()?)));
+        }
+
+        match self.next() {
+            Some(Token::Open) => {
+                let node = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(node),
+                    _ => Err("missing ')'".to_owned()),
+                }
+            }
+            Some(Token::Ident(name)) => {
+                let field =
+                    Field::from_name(name).ok_or_else(|| format!("unknown field {}", name))?;
+                self.comparison(field)
+            }
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end".to_owned()),
+        }
+    }
+
+    fn comparison(&mut self, field: Field) -> std::result::Result<Node, String> {
+        let op = match self.next() {
+            Some(Token::Op(op)) => *op,
+            Some(Token::Ident(i)) if i == "contains" => "contains",
+            _ => return Err("expected an operator after a field".to_owned()),
+        };
+
+        let value = match self.next() {
+            Some(Token::Str(s)) => s.clone(),
+            _ => return Err(format!("expected a string after {}", op)),
+        };
+
+        let regex = || Regex::new(&value).map_err(|e| format!("invalid regex: {}", e));
+
+        let operator = match op {
+            "==" => Operator::Equals(value.clone()),
+            "!=" => Operator::NotEquals(value.clone()),
+            "=~" => Operator::Matches(regex()?),
+            "!~" => Operator::NotMatches(regex()?),
+            _ => Operator::Contains(value.clone()),
+        };
+
+        Ok(Node::Compare(field, operator))
+    }
+}
@@ -0,0 +1,102 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::{
+    config::{FilterAction, FilterConfig, FilterField},
+    error::Error,
+    expression::Expression,
+    outputs::Entry,
+    Result,
+};
+
+/// Include and exclude rules deciding which entries are pushed.
+#[derive(Default)]
+pub struct Filters {
+    include: Vec<Filter>,
+    exclude: Vec<Filter>,
+}
+
+enum Filter {
+    Regex {
+        fields: Vec<FilterField>,
+        regex: Regex,
+    },
+    Expression(Expression),
+}
+
+impl Filters {
+    pub fn new(configs: Vec<FilterConfig>) -> Result<Self> {
+        let mut filters = Self::default();
+
+        for config in configs {
+            let filter =
+                match (config.regex, config.keywords.is_empty(), config.expression) {
+                    (None, true, Some(expression)) => {
+                        Filter::Expression(Expression::parse(&expression)?)
+                    }
+                    (Some(regex), true, None) => {
+                        Filter::new(config.fields, &regex, config.case_sensitive)?
+                    }
+                    (None, false, None) => {
+                        let pattern = config
+                            .keywords
+                            .iter()
+                            .map(|keyword| regex::escape(keyword))
+                            .collect::<Vec<String>>()
+                            .join("|");
+                        Filter::new(config.fields, &pattern, config.case_sensitive)?
+                    }
+                    _ => return Err(Error::Config(
+                        "filters: exactly one of `regex`, `keywords` or `expression` has to be set"
+                            .to_owned(),
+                    )),
+                };
+
+            match config.action {
+                FilterAction::Include => filters.include.push(filter),
+                FilterAction::Exclude => filters.exclude.push(filter),
+            }
+        }
+
+        Ok(filters)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// An entry passes if it matches any include rule (or there are none)
+    /// and no exclude rule.
+    pub fn matches(&self, input: &str, entry: &Entry) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|f| f.matches(input, entry)))
+            && !self.exclude.iter().any(|f| f.matches(input, entry))
+    }
+}
+
+impl Filter {
+    fn new(fields: Vec<FilterField>, pattern: &str, case_sensitive: bool) -> Result<Self> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(!case_sensitive)
+            .build()
+            .map_err(|e| Error::Config(format!("filters: invalid regex: {}", e)))?;
+
+        Ok(Filter::Regex { fields, regex })
+    }
+
+    fn matches(&self, input: &str, entry: &Entry) -> bool {
+        let (fields, regex) = match self {
+            Filter::Regex { fields, regex } => (fields, regex),
+            Filter::Expression(expression) => return expression.matches(input, entry),
+        };
+
+        fields.iter().any(|field| match field {
+            FilterField::Title => regex.is_match(&entry.title),
+            FilterField::Description => regex.is_match(&entry.description),
+            FilterField::Author => entry
+                .author
+                .as_ref()
+                .is_some_and(|author| regex.is_match(author)),
+            FilterField::Categories => entry.categories.iter().any(|c| regex.is_match(c)),
+            FilterField::Url => regex.is_match(&entry.url),
+        })
+    }
+}
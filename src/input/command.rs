@@ -0,0 +1,245 @@
+use std::{collections::HashMap, process::Stdio, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use feed_rs::{
+    model::{Image, MediaObject, MediaThumbnail},
+    parser,
+};
+use log::{debug, warn};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::process;
+
+use super::{Feeds, Hints, InputTrait};
+use crate::{config::CommandFormat, error::Error, Result};
+
+/// Program printing a feed, or entries as JSON lines, on every poll.
+pub struct Command {
+    command: String,
+    arguments: Vec<String>,
+    env: HashMap<String, String>,
+    timeout: Duration,
+    format: CommandFormat,
+}
+
+/// An entry in the JSON the custom output writes, its `tags` are the ones of
+/// the input reading it.
+#[derive(Deserialize)]
+struct Line {
+    id: Option<String>,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    description: String,
+    author: Option<String>,
+    #[serde(default)]
+    url: String,
+    timestamp: DateTime<Utc>,
+    updated: Option<DateTime<Utc>>,
+    #[serde(default)]
+    categories: Vec<String>,
+    #[serde(default)]
+    links: Vec<String>,
+    #[serde(default)]
+    enclosures: Vec<LineEnclosure>,
+    image: Option<String>,
+    feed_title: Option<String>,
+    feed_icon: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LineEnclosure {
+    url: String,
+    media_type: Option<String>,
+    length: Option<u64>,
+}
+
+impl Command {
+    pub fn new(
+        command: String,
+        arguments: Vec<String>,
+        env: HashMap<String, String>,
+        timeout: Duration,
+        format: CommandFormat,
+    ) -> Self {
+        Self {
+            command,
+            arguments,
+            env,
+            timeout,
+            format,
+        }
+    }
+
+    async fn run(&self) -> Result<Vec<u8>> {
+        debug!("running input command: {}", &self.command);
+
+        let child = process::Command::new(&self.command)
+            .args(&self.arguments)
+            .envs(&self.env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let output = tokio::time::timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                Error::Command(format!(
+                    "{} timed out after {:?}",
+                    self.command, self.timeout
+                ))
+            })??;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+
+            return Err(Error::Command(format!(
+                "{} failed with {}: {}",
+                self.command,
+                output.status,
+                stderr.trim()
+            )));
+        }
+
+        Ok(output.stdout)
+    }
+}
+
+#[async_trait]
+impl InputTrait for Command {
+    async fn fetch(&self, hints: bool) -> Result<(Feeds, Hints)> {
+        let stdout = self.run().await?;
+
+        match self.format {
+            CommandFormat::Feed => {
+                let feed = parser::parse(&stdout[..])?;
+
+                let hints = match hints {
+                    true => Hints::new(&feed, &stdout, None),
+                    false => Hints::default(),
+                };
+
+                Ok((vec![(String::new(), feed)], hints))
+            }
+            CommandFormat::JsonLines => Ok((json_lines(&self.command, &stdout)?, Hints::default())),
+        }
+    }
+
+    fn url(&self) -> &str {
+        &self.command
+    }
+}
+
+/// Reads entries as JSON lines, one feed per `feed_title`.
+fn json_lines(command: &str, stdout: &[u8]) -> Result<Feeds> {
+    // (title, icon, items and their images) in the order the feeds first
+    // show up
+    type Items = Vec<(serde_json::Value, Option<String>)>;
+    let mut feeds: Vec<(Option<String>, Option<String>, Items)> = Vec::new();
+
+    for raw in String::from_utf8_lossy(stdout).lines() {
+        if raw.trim().is_empty() {
+            continue;
+        }
+
+        let line: Line = match serde_json::from_str(raw) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("skipping invalid entry from {}: {}", command, e);
+                continue;
+            }
+        };
+
+        // an entry without id and url is told apart by its content
+        let id = line
+            .id
+            .or_else(|| Some(line.url.clone()).filter(|url| !url.is_empty()))
+            .unwrap_or_else(|| hex::encode(Sha256::digest(raw.trim().as_bytes())));
+
+        // json feed items, parsed with the feeds of the other inputs
+        let item = json!({
+            "id": id,
+            "url": line.url,
+            "external_url": line.links.first(),
+            "title": line.title,
+            "summary": line.description,
+            "date_published": line.timestamp.to_rfc3339(),
+            "date_modified": line.updated.map(|updated| updated.to_rfc3339()),
+            "author": line.author.map(|name| json!({ "name": name })),
+            "tags": line.categories,
+            "attachments": line.enclosures.iter().map(|enclosure| json!({
+                "url": enclosure.url,
+                "mime_type": enclosure
+                    .media_type
+                    .as_deref()
+                    .unwrap_or("application/octet-stream"),
+                "size_in_bytes": enclosure.length,
+            })).collect::<Vec<_>>(),
+        });
+
+        match feeds
+            .iter_mut()
+            .find(|(title, ..)| *title == line.feed_title)
+        {
+            Some((_, icon, items)) => {
+                if icon.is_none() {
+                    *icon = line.feed_icon;
+                }
+                items.push((item, line.image));
+            }
+            None => feeds.push((line.feed_title, line.feed_icon, vec![(item, line.image)])),
+        }
+    }
+
+    feeds
+        .into_iter()
+        .map(|(title, icon, items)| {
+            let (items, images): (Vec<_>, Vec<_>) = items.into_iter().unzip();
+
+            let document = json!({
+                "version": "https://jsonfeed.org/version/1.1",
+                "title": title.as_deref().unwrap_or_default(),
+                "favicon": icon,
+                "items": items,
+            });
+
+            let mut feed = parser::parse(document.to_string().as_bytes())?;
+            let key = title.unwrap_or_default();
+            if key.is_empty() {
+                feed.title = None;
+            }
+
+            // feed_rs keeps attachments as plain links and drops item images
+            for (entry, image) in feed.entries.iter_mut().zip(images) {
+                for link in entry.links.iter_mut() {
+                    if link.media_type.is_some() {
+                        link.rel = Some("enclosure".to_owned());
+                    }
+                }
+
+                if let Some(image) = image {
+                    entry.media.push(MediaObject {
+                        thumbnails: vec![MediaThumbnail {
+                            image: Image {
+                                uri: image,
+                                title: None,
+                                link: None,
+                                width: None,
+                                height: None,
+                                description: None,
+                            },
+                            time: None,
+                        }],
+                        ..Default::default()
+                    });
+                }
+            }
+
+            Ok((key, feed))
+        })
+        .collect()
+}
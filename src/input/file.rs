@@ -0,0 +1,172 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use feed_rs::parser;
+use log::warn;
+use notify::{recommended_watcher, RecommendedWatcher, RecursiveMode, Watcher};
+use reqwest::Url;
+use tokio::{fs, sync::mpsc};
+
+use super::{Feeds, Hints, InputTrait};
+use crate::{error::Error, Result};
+
+/// Feed file, or every feed file of a directory, on disk.
+pub struct File {
+    path: PathBuf,
+    url: String,
+    watch: bool,
+    /// kept alive while the input runs
+    watcher: Option<RecommendedWatcher>,
+}
+
+impl File {
+    pub fn new(path: PathBuf, watch: bool) -> Self {
+        let url = Url::from_file_path(&path)
+            .map(String::from)
+            .unwrap_or_else(|_| path.display().to_string());
+
+        Self {
+            path,
+            url,
+            watch,
+            watcher: None,
+        }
+    }
+
+    pub fn from_url(url: &str) -> Result<Self> {
+        let path = Url::parse(url)
+            .ok()
+            .and_then(|url| url.to_file_path().ok())
+            .ok_or_else(|| Error::Config(format!("inputs: invalid file url: {}", url)))?;
+
+        Ok(Self::new(path, false))
+    }
+}
+
+#[async_trait]
+impl InputTrait for File {
+    async fn fetch(&self, hints: bool) -> Result<(Feeds, Hints)> {
+        // a file between two writes or a directory being replaced is read
+        // again on the next poll
+        let metadata = match fs::metadata(&self.path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                warn!("feed file {} not found", self.path.display());
+                return Ok((Vec::new(), Hints::default()));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let paths = if metadata.is_dir() {
+            let mut paths = Vec::new();
+
+            let mut dir = fs::read_dir(&self.path).await?;
+            while let Some(entry) = dir.next_entry().await? {
+                let is_file = match entry.file_type().await {
+                    Ok(file_type) => file_type.is_file(),
+                    Err(e) if e.kind() == ErrorKind::NotFound => false,
+                    Err(e) => return Err(e.into()),
+                };
+
+                if is_file && !is_hidden(&entry.path()) {
+                    paths.push(entry.path());
+                }
+            }
+
+            paths.sort();
+            paths
+        } else {
+            vec![self.path.clone()]
+        };
+
+        let mut feeds = Vec::with_capacity(paths.len());
+        let mut hint_body = None;
+
+        for path in paths.iter() {
+            let body = match fs::read(path).await {
+                Ok(body) => body,
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    warn!("skipping vanished feed file {}", path.display());
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            // a file being written or not meant as a feed doesn't stop the
+            // others
+            match parser::parse(&body[..]) {
+                Ok(feed) => {
+                    feeds.push((path.display().to_string(), feed));
+                    hint_body = Some(body);
+                }
+                Err(e) => warn!("skipping feed file {}: {}", path.display(), e),
+            }
+        }
+
+        let hints = match (&feeds[..], hint_body) {
+            ([(_, feed)], Some(body)) if hints => Hints::new(feed, &body, None),
+            _ => Hints::default(),
+        };
+
+        Ok((feeds, hints))
+    }
+
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn changes(&mut self) -> Result<Option<mpsc::Receiver<()>>> {
+        if !self.watch {
+            return Ok(None);
+        }
+
+        // the directory of a file is watched, files replaced by a rename
+        // would be lost otherwise
+        let (dir, file) = match self.path.is_dir() {
+            true => (self.path.clone(), None),
+            false => (
+                self.path
+                    .parent()
+                    .filter(|dir| !dir.as_os_str().is_empty())
+                    .unwrap_or_else(|| Path::new("."))
+                    .to_owned(),
+                self.path.file_name().map(ToOwned::to_owned),
+            ),
+        };
+
+        // a single pending change stands for all the ones since the last
+        // read
+        let (tx, rx) = mpsc::channel(1);
+
+        let mut watcher = recommended_watcher(move |res: notify::Result<notify::Event>| {
+            let event = match res {
+                Ok(event) if !event.kind.is_access() => event,
+                _ => return,
+            };
+
+            let relevant = event.paths.iter().any(|path| match &file {
+                Some(file) => path.file_name() == Some(file.as_os_str()),
+                None => !is_hidden(path),
+            });
+
+            if relevant {
+                tx.try_send(()).ok();
+            }
+        })?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+        self.watcher = Some(watcher);
+
+        Ok(Some(rx))
+    }
+}
+
+/// Temporary files of editors and atomic writes start with a dot.
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('.'))
+}
@@ -0,0 +1,640 @@
+mod command;
+mod file;
+mod rss;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Timelike, Utc, Weekday};
+use feed_rs::{model::Feed, parser};
+use reqwest::Client;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono_tz::Tz;
+use hyper::body::Bytes;
+use rand::Rng;
+use regex::Regex;
+use tokio::sync::{broadcast::Receiver, mpsc};
+use tokio::time::Instant;
+
+use log::{debug, error};
+use once_cell::sync::Lazy;
+
+use crate::{
+    config::{AdaptiveConfig, DescriptionConfig, InputConfig, InputKind, Overflow},
+    error::Error,
+    expression::Expression,
+    filter::Filters,
+    outputs::{Entry, Output},
+    websub::WebSub,
+    Result,
+};
+
+use self::{command::Command, file::File, rss::Rss};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(60 * 30);
+
+/// Time given to a writer to finish before a changed source is read.
+const WATCH_DELAY: Duration = Duration::from_millis(500);
+
+/// Number of the latest entries the publishing frequency is computed from.
+const FREQUENCY_ENTRIES: usize = 10;
+
+/// Skip hints of rss feeds, which feed_rs doesn't keep.
+static SKIP_HOURS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<skipHours>(.*?)</skipHours>").unwrap());
+static SKIP_DAYS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<skipDays>(.*?)</skipDays>").unwrap());
+static HOUR: Lazy<Regex> = Lazy::new(|| Regex::new(r"<hour>\s*([^<\s]+)\s*</hour>").unwrap());
+static DAY: Lazy<Regex> = Lazy::new(|| Regex::new(r"<day>\s*([^<\s]+)\s*</day>").unwrap());
+
+pub struct Input {
+    name: String,
+
+    input: Box<dyn InputTrait + Send + Sync>,
+    tags: Vec<String>,
+    description: DescriptionConfig,
+    filters: Filters,
+    interval: Duration,
+    cron: Option<(cron::Schedule, Tz)>,
+    startup_offset: Option<Duration>,
+    jitter: Option<Duration>,
+    adaptive: Option<AdaptiveConfig>,
+    retry_limit: usize,
+    retries_left: usize,
+    /// how far every feed of the input was pushed, by key
+    cursors: HashMap<String, Cursor>,
+    max_age: Option<chrono::Duration>,
+    future_tolerance: Option<chrono::Duration>,
+    max_entries_per_poll: Option<usize>,
+    overflow: Overflow,
+    /// entries over `max_entries_per_poll` left for the next polls
+    deferred: Vec<Entry>,
+
+    outputs: Vec<Arc<Output>>,
+    routes: Vec<Route>,
+
+    websub: Option<Arc<WebSub>>,
+    /// content pushed by the websub hub
+    pushed: Option<mpsc::Receiver<Bytes>>,
+    /// changes of a watched source, replacing the polls after the first one
+    changed: Option<mpsc::Receiver<()>>,
+}
+
+/// Feeds of an input keyed by their source within it, e.g. the file they were
+/// read from.
+type Feeds = Vec<(String, Feed)>;
+
+/// Where the pushes of a feed stopped.
+#[derive(Default)]
+struct Cursor {
+    last_date: Option<DateTime<Utc>>,
+    /// ids and real dates of the future dated entries already pushed, kept
+    /// until `last_date` passes them
+    pushed_future: Vec<(String, DateTime<Utc>)>,
+}
+
+/// Outputs receiving only the entries matching `condition`.
+pub struct Route {
+    pub condition: Expression,
+    pub outputs: Vec<Arc<Output>>,
+    /// matching entries skip the outputs of the input's name and tags
+    pub exclusive: bool,
+}
+
+impl Input {
+    pub fn new(
+        name: String,
+        config: InputConfig,
+        outputs: Vec<Arc<Output>>,
+        routes: Vec<Route>,
+        websub: Option<&Arc<WebSub>>,
+        client: Client,
+    ) -> Result<Self> {
+        if let Some(adaptive) = config.adaptive {
+            if adaptive.min_interval > adaptive.max_interval {
+                return Err(Error::Config(format!(
+                    "inputs: {}: adaptive min_interval is greater than max_interval",
+                    name
+                )));
+            }
+        }
+
+        let mut input: Box<dyn InputTrait + Send + Sync> = match config.kind {
+            InputKind::Rss { url } if url.starts_with("file://") => Box::new(File::from_url(&url)?),
+            InputKind::Rss { url } => Box::new(Rss::new(url, client)),
+            InputKind::File { path, watch } => Box::new(File::new(path, watch)),
+            InputKind::Command {
+                command,
+                arguments,
+                env,
+                timeout,
+                format,
+            } => Box::new(Command::new(command, arguments, env, timeout, format)),
+        };
+        let changed = input.changes()?;
+
+        let websub = websub.filter(|_| config.websub && input.websub()).cloned();
+        let pushed = websub.as_ref().map(|websub| websub.register(&name));
+
+        Ok(Self {
+            name,
+
+            input,
+            tags: config.tags,
+            description: config.description,
+            filters: Filters::new(config.filters)?,
+            interval: config.interval.unwrap_or(DEFAULT_INTERVAL),
+            cron: config
+                .cron
+                .as_deref()
+                .map(parse_cron)
+                .transpose()?
+                .map(|schedule| (schedule, config.timezone)),
+            startup_offset: config.startup_offset,
+            jitter: config.jitter,
+            adaptive: config.adaptive,
+            retries_left: config.retry_limit,
+            retry_limit: config.retry_limit,
+            cursors: HashMap::new(),
+            max_age: config.max_age.map(chrono_duration).transpose()?,
+            future_tolerance: config.future_tolerance.map(chrono_duration).transpose()?,
+            max_entries_per_poll: config.max_entries_per_poll,
+            overflow: config.overflow,
+            deferred: Vec::new(),
+            outputs,
+            routes,
+            websub,
+            pushed,
+            changed,
+        })
+    }
+
+    async fn push(&self, entries: &[Entry]) -> Result<()> {
+        // an output reached through the input's name, its tags and several
+        // routes gets an entry once, outputs are shared by id
+        let mut pushes: Vec<(&Arc<Output>, Vec<Entry>)> = self
+            .outputs
+            .iter()
+            .map(|output| (output, Vec::new()))
+            .collect();
+
+        for entry in entries {
+            let routes: Vec<&Route> = self
+                .routes
+                .iter()
+                .filter(|route| route.condition.matches(&self.name, entry))
+                .collect();
+
+            let outputs = match routes.iter().any(|route| route.exclusive) {
+                true => &[][..],
+                false => &self.outputs[..],
+            };
+
+            let mut targets: Vec<&Arc<Output>> = Vec::new();
+            for output in outputs
+                .iter()
+                .chain(routes.iter().flat_map(|route| route.outputs.iter()))
+            {
+                if !targets.iter().any(|target| Arc::ptr_eq(target, output)) {
+                    targets.push(output);
+                }
+            }
+
+            for output in targets {
+                match pushes.iter_mut().find(|(o, _)| Arc::ptr_eq(o, output)) {
+                    Some((_, pushed)) => pushed.push(entry.clone()),
+                    None => pushes.push((output, vec![entry.clone()])),
+                }
+            }
+        }
+
+        for (output, entries) in pushes {
+            if !entries.is_empty() {
+                output.push(&self.name, &entries).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Time of the next poll according to the hints of the feed.
+    fn adaptive_poll(&self, adaptive: &AdaptiveConfig, hints: &Hints) -> Instant {
+        let interval = [hints.ttl, hints.max_age]
+            .into_iter()
+            .flatten()
+            .fold(hints.frequency.unwrap_or(self.interval), Duration::max)
+            .clamp(adaptive.min_interval, adaptive.max_interval);
+
+        let mut next = match chrono::Duration::from_std(interval)
+            .ok()
+            .and_then(|interval| Utc::now().checked_add_signed(interval))
+        {
+            Some(next) => next,
+            None => return Instant::now() + interval,
+        };
+
+        // skipped hours and days are in GMT, a week is the longest skip
+        for _ in 0..24 * 7 {
+            if !hints.skip_hours.contains(&next.hour())
+                && !hints.skip_days.contains(&next.weekday())
+            {
+                break;
+            }
+
+            let hour = next
+                .date_naive()
+                .and_hms_opt(next.hour(), 0, 0)
+                .unwrap()
+                .and_utc();
+            next = hour + chrono::Duration::hours(1);
+        }
+
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        Instant::now() + wait
+    }
+
+    /// Time of the poll after the one planned at `last`.
+    fn next_poll(&self, last: Instant) -> Instant {
+        let next = match self.cron.as_ref() {
+            Some((schedule, timezone)) => {
+                let now = Utc::now();
+                // a wake up slightly early must not plan the same tick again
+                let after = (now + chrono::Duration::seconds(1)).with_timezone(timezone);
+                schedule
+                    .after(&after)
+                    .next()
+                    .and_then(|next| (next.with_timezone(&Utc) - now).to_std().ok())
+                    .map_or(last + self.interval, |wait| Instant::now() + wait)
+            }
+            None => last + self.interval,
+        };
+
+        // polls missed while busy aren't made up for
+        next.max(Instant::now())
+    }
+
+    pub async fn watch(mut self, mut kill: Receiver<()>) -> Result<()> {
+        let mut next = Instant::now() + random_delay(self.startup_offset);
+        let mut polled = false;
+
+        loop {
+            tokio::select! {
+                biased;
+                _ = kill.recv() => break,
+                Some(body) = recv_pushed(&mut self.pushed) => {
+                    match parser::parse(&body[..]) {
+                        Ok(feed) => self.process(vec![(String::new(), feed)]).await?,
+                        Err(e) => error!("invalid websub content for \"{}\": {}", self.name, e),
+                    }
+                    continue;
+                },
+                Some(()) = recv_changed(&mut self.changed) => {},
+                _ = tokio::time::sleep_until(next + random_delay(self.jitter)),
+                    if !polled || self.changed.is_none() => {},
+            };
+
+            next = self.next_poll(next);
+
+            // the hub pushes the updates while the subscription lasts
+            if let Some(websub) = self.websub.as_ref() {
+                if websub.is_active(&self.name) {
+                    websub.renew(&self.name).await;
+                    continue;
+                }
+            }
+
+            let (feeds, hints) = match self.input.fetch(self.adaptive.is_some()).await {
+                Ok(c) => c,
+                Err(e) => {
+                    if is_retriable(&e) && self.retries_left > 0 {
+                        error!("error while getting items: {}", e);
+                        self.retries_left -= 1;
+                        continue;
+                    } else {
+                        return Err(e);
+                    }
+                }
+            };
+
+            polled = true;
+
+            if let Some(adaptive) = self.adaptive.as_ref().filter(|_| self.cron.is_none()) {
+                next = self.adaptive_poll(adaptive, &hints);
+                debug!(
+                    "next poll of \"{}\" in {:?}",
+                    self.name,
+                    next - Instant::now()
+                );
+            }
+
+            if let (Some(websub), [(_, feed)]) = (self.websub.as_ref(), &feeds[..]) {
+                websub.subscribe(&self.name, self.input.url(), feed).await;
+            }
+
+            self.process(feeds).await?;
+
+            if self.retries_left != self.retry_limit {
+                self.retries_left = self.retry_limit;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pushes the new entries of polled or pushed feeds.
+    async fn process(&mut self, mut feeds: Feeds) -> Result<()> {
+        let now = Utc::now();
+
+        // entries dated too far ahead would pin `last_date`, they are
+        // dated now and pushed once instead
+        let mut futures: Vec<Vec<(String, DateTime<Utc>)>> = Vec::with_capacity(feeds.len());
+        for (_, feed) in feeds.iter_mut() {
+            let mut future = Vec::new();
+            if let Some(tolerance) = self.future_tolerance {
+                for entry in feed.entries.iter_mut() {
+                    if let Some(published) = entry.published.filter(|&p| p > now + tolerance) {
+                        entry.published = Some(now);
+                        future.push((entry.id.clone(), published));
+                    }
+                }
+            }
+            futures.push(future);
+        }
+
+        // the first poll only marks what's already in the feeds, a feed
+        // showing up later is new as a whole
+        let first = self.cursors.values().all(|c| c.last_date.is_none());
+
+        // every feed is compared with its own latest entry, a directory's
+        // files aren't in step
+        let mut items: Vec<(&Feed, &feed_rs::model::Entry)> = Vec::new();
+        let mut new_entries: Vec<(&Feed, &feed_rs::model::Entry)> = Vec::new();
+        let mut polled = Vec::with_capacity(feeds.len());
+
+        for ((key, feed), future) in feeds.iter().zip(futures) {
+            let is_future = |id: &String| future.iter().any(|(future, _)| future == id);
+            let cursor = self.cursors.entry(key.clone()).or_default();
+
+            let latest = feed
+                .entries
+                .iter()
+                .filter(|e| !is_future(&e.id))
+                .filter_map(|e| e.published)
+                .max();
+
+            if first {
+                cursor.last_date = latest;
+                cursor.pushed_future = future.clone();
+            }
+
+            for entry in feed.entries.iter() {
+                items.push((feed, entry));
+
+                // pushed while ahead, also once its real date has come
+                let pushed = cursor.pushed_future.iter().any(|(id, _)| *id == entry.id);
+                let new =
+                    !pushed && (is_future(&entry.id) || entry.published.gt(&cursor.last_date));
+                let recent = self
+                    .max_age
+                    .zip(entry.published)
+                    .is_none_or(|(max_age, published)| published >= now - max_age);
+
+                if new && recent {
+                    new_entries.push((feed, entry));
+                }
+            }
+
+            polled.push((
+                key.clone(),
+                Cursor {
+                    last_date: latest,
+                    pushed_future: future,
+                },
+            ));
+        }
+
+        #[cfg(debug_assertions)]
+        let new_entries = {
+            use std::env;
+
+            if let Ok(mode) = env::var("TEST_MODE") {
+                if mode == "1" {
+                    items.iter().copied().take(2).collect()
+                } else {
+                    new_entries
+                }
+            } else {
+                new_entries
+            }
+        };
+
+        if new_entries.is_empty() && self.deferred.is_empty() {
+            self.advance(polled);
+            return Ok(());
+        }
+
+        // entries deferred by the previous polls go first
+        let mut entries: Vec<Entry> = std::mem::take(&mut self.deferred);
+        entries.extend(
+            new_entries
+                .iter()
+                .map(|&(feed, e)| Entry::new(e.clone(), feed, &self.tags, self.description))
+                .filter(|entry| self.filters.matches(&self.name, entry)),
+        );
+
+        let feed_title = feeds
+            .first()
+            .and_then(|(_, feed)| feed.title.as_ref())
+            .map_or(&self.name, |t| &t.content);
+
+        if let Some(max) = self.max_entries_per_poll.filter(|&max| entries.len() > max) {
+            let overflow = entries.split_off(max);
+
+            match self.overflow {
+                Overflow::Drop => {
+                    debug!(
+                        "dropping {} items from \"{}\" feed",
+                        overflow.len(),
+                        feed_title
+                    )
+                }
+                Overflow::Defer => self.deferred = overflow,
+                Overflow::Summary => entries.push(Entry::overflow(
+                    overflow.len(),
+                    feeds.first().map(|(_, feed)| feed),
+                    &self.name,
+                    self.input.url(),
+                    &self.tags,
+                )),
+            }
+        }
+
+        debug!(
+            "pushing {} of {} items from \"{}\" feed",
+            entries.len(),
+            new_entries.len(),
+            feed_title,
+        );
+
+        if !entries.is_empty() {
+            self.push(&entries).await?;
+        }
+
+        self.advance(polled);
+
+        Ok(())
+    }
+
+    /// Moves the `last_date` of every feed to the latest entry of the poll,
+    /// the future dated entries are remembered until it passes their real
+    /// date.
+    fn advance(&mut self, polled: Vec<(String, Cursor)>) {
+        for (key, poll) in polled {
+            let cursor = self.cursors.entry(key).or_default();
+            cursor.last_date = cursor.last_date.max(poll.last_date);
+
+            let last_date = cursor.last_date;
+            cursor
+                .pushed_future
+                .retain(|&(_, published)| Some(published) > last_date);
+
+            for (id, published) in poll.pushed_future {
+                if !cursor.pushed_future.iter().any(|(pushed, _)| *pushed == id) {
+                    cursor.pushed_future.push((id, published));
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+trait InputTrait {
+    /// Fetches the feeds, with their polling hints when `hints` is set.
+    async fn fetch(&self, hints: bool) -> Result<(Feeds, Hints)>;
+
+    /// Where the feed comes from.
+    fn url(&self) -> &str;
+
+    /// Starts watching the source, the receiver gets a message on changes.
+    fn changes(&mut self) -> Result<Option<mpsc::Receiver<()>>> {
+        Ok(None)
+    }
+
+    /// Whether the feed can be pushed by a websub hub.
+    fn websub(&self) -> bool {
+        false
+    }
+}
+
+/// Content pushed by the websub hub, never resolves without a subscription.
+async fn recv_pushed(pushed: &mut Option<mpsc::Receiver<Bytes>>) -> Option<Bytes> {
+    match pushed {
+        Some(pushed) => pushed.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// A change of the watched source, once its writer is done.
+async fn recv_changed(changed: &mut Option<mpsc::Receiver<()>>) -> Option<()> {
+    let changed = match changed {
+        Some(changed) => changed,
+        None => return std::future::pending().await,
+    };
+
+    changed.recv().await?;
+
+    tokio::time::sleep(WATCH_DELAY).await;
+    while changed.try_recv().is_ok() {}
+
+    Some(())
+}
+
+/// Polling hints of a feed.
+#[derive(Default)]
+struct Hints {
+    ttl: Option<Duration>,
+    max_age: Option<Duration>,
+    /// average time between the latest entries
+    frequency: Option<Duration>,
+    skip_hours: Vec<u32>,
+    skip_days: Vec<Weekday>,
+}
+
+impl Hints {
+    fn new(feed: &Feed, body: &[u8], max_age: Option<Duration>) -> Self {
+        let mut dates: Vec<DateTime<Utc>> =
+            feed.entries.iter().filter_map(|e| e.published).collect();
+        dates.sort_unstable_by(|a, b| b.cmp(a));
+        dates.truncate(FREQUENCY_ENTRIES);
+
+        let frequency = match (dates.first(), dates.last()) {
+            (Some(newest), Some(oldest)) if dates.len() > 1 => (*newest - *oldest)
+                .to_std()
+                .ok()
+                .map(|span| span / (dates.len() as u32 - 1)),
+            _ => None,
+        };
+
+        let body = String::from_utf8_lossy(body);
+        let skipped = |list: &Regex, item: &Regex| -> Vec<String> {
+            list.captures(&body)
+                .map(|list| {
+                    item.captures_iter(&list[1])
+                        .map(|item| item[1].to_owned())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        Self {
+            ttl: feed
+                .ttl
+                .map(|minutes| Duration::from_secs(minutes as u64 * 60)),
+            max_age,
+            frequency,
+            skip_hours: skipped(&SKIP_HOURS, &HOUR)
+                .iter()
+                .filter_map(|hour| hour.parse().ok())
+                .collect(),
+            skip_days: skipped(&SKIP_DAYS, &DAY)
+                .iter()
+                .filter_map(|day| day.parse().ok())
+                .collect(),
+        }
+    }
+}
+
+/// Parses a cron expression, the seconds can be left out.
+fn parse_cron(expression: &str) -> Result<cron::Schedule> {
+    let expression = match expression.split_whitespace().count() {
+        5 => format!("0 {}", expression),
+        _ => expression.to_owned(),
+    };
+
+    expression.parse().map_err(|e| {
+        Error::Config(format!(
+            "inputs: invalid cron expression {}: {}",
+            expression, e
+        ))
+    })
+}
+
+/// A random delay shorter than `max`.
+fn random_delay(max: Option<Duration>) -> Duration {
+    match max {
+        Some(max) if !max.is_zero() => rand::thread_rng().gen_range(Duration::ZERO..max),
+        _ => Duration::ZERO,
+    }
+}
+
+fn chrono_duration(duration: Duration) -> Result<chrono::Duration> {
+    chrono::Duration::from_std(duration)
+        .map_err(|_| Error::Config(format!("inputs: duration out of range: {:?}", duration)))
+}
+
+fn is_retriable(err: &Error) -> bool {
+    match err {
+        Error::Request(e) => e.is_timeout() || e.is_connect(),
+        Error::Command(_) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use feed_rs::parser;
+use reqwest::{header::CACHE_CONTROL, Client};
+
+use super::{Feeds, Hints, InputTrait};
+use crate::Result;
+
+/// RSS, Atom or JSON feed fetched over http.
+pub struct Rss {
+    url: String,
+    client: Client,
+}
+
+impl Rss {
+    pub fn new(url: String, client: Client) -> Self {
+        Self { url, client }
+    }
+}
+
+#[async_trait]
+impl InputTrait for Rss {
+    async fn fetch(&self, hints: bool) -> Result<(Feeds, Hints)> {
+        let res = self
+            .client
+            .get(&self.url)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let max_age = res
+            .headers()
+            .get(CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok())
+            .and_then(max_age);
+
+        let body = res.bytes().await?;
+
+        let feed = parser::parse(&body[..])?;
+
+        let hints = match hints {
+            true => Hints::new(&feed, &body, max_age),
+            false => Hints::default(),
+        };
+
+        Ok((vec![(String::new(), feed)], hints))
+    }
+
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn websub(&self) -> bool {
+        true
+    }
+}
+
+/// Reads `max-age` from a Cache-Control header.
+fn max_age(cache_control: &str) -> Option<Duration> {
+    cache_control
+        .split(',')
+        .filter_map(|directive| directive.trim().strip_prefix("max-age="))
+        .find_map(|seconds| seconds.parse().ok())
+        .map(Duration::from_secs)
+}
@@ -1,11 +1,14 @@
 mod config;
 mod error;
+mod expression;
+mod filter;
 mod input;
 mod outputs;
+mod websub;
 
 use gumdrop::Options;
 
-use std::process;
+use std::{collections::HashMap, process, sync::Arc};
 
 use futures::future;
 use reqwest::Client;
@@ -17,7 +20,13 @@ use tokio::{
     task::JoinHandle,
 };
 
-use crate::{config::Config, input::Input, outputs::Output};
+use crate::{
+    config::{Config, OutputConfig},
+    expression::Expression,
+    input::{Input, Route},
+    outputs::{Output, OutputContext},
+    websub::WebSub,
+};
 
 pub type Result<T> = std::result::Result<T, crate::error::Error>;
 
@@ -65,7 +74,13 @@ async fn main() -> Result<()> {
 
     let client = build_client()?;
 
-    let tasks = watch_inputs(config, client)?;
+    let tasks = match watch_inputs(config, client).await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("Error while setting up outputs: {}", e);
+            process::exit(1);
+        }
+    };
 
     future::try_join_all(tasks).await?;
 
@@ -81,32 +96,83 @@ fn build_client() -> Result<Client> {
     Ok(client)
 }
 
-fn watch_inputs(config: Config, client: Client) -> Result<Vec<JoinHandle<Result<()>>>> {
+async fn shared_output(
+    instances: &mut HashMap<String, Arc<Output>>,
+    output_config: &OutputConfig,
+    context: &OutputContext,
+) -> Result<Arc<Output>> {
+    if let Some(output) = instances.get(&output_config.id) {
+        return Ok(output.clone());
+    }
+
+    let output = Arc::new(Output::new(output_config.clone(), context).await?);
+    instances.insert(output_config.id.clone(), output.clone());
+
+    Ok(output)
+}
+
+async fn watch_inputs(mut config: Config, client: Client) -> Result<Vec<JoinHandle<Result<()>>>> {
     let mut tasks = Vec::with_capacity(config.inputs.len());
     let (tx, _) = broadcast::channel(tasks.capacity());
 
-    for (name, input_config) in config.inputs.into_iter() {
+    // outputs are shared by all the inputs forwarding to them
+    let mut instances = HashMap::new();
+    let context = OutputContext::new(
+        client.clone(),
+        config.dedupe.clone(),
+        config.state_dir.clone(),
+    );
+    let websub = config
+        .websub
+        .take()
+        .map(|websub_config| Arc::new(WebSub::new(websub_config, client.clone())));
+
+    for (name, input_config) in std::mem::take(&mut config.inputs) {
         info!("Start watcher for \"{}\"", &name);
 
+        // name and tag based outputs
         let mut outputs = Vec::new();
-
-        // name based outputs
-        if let Some(output_configs) = config.outputs.get(&name) {
-            for output_config in output_configs {
-                outputs.push(Output::new(output_config.clone(), client.clone()));
+        let mut ids = Vec::new();
+        for output_config in config.outputs_for(&name, &input_config.tags) {
+            // an output reachable through several keys gets the entries once
+            if ids.contains(&&output_config.id) {
+                continue;
             }
+            ids.push(&output_config.id);
+
+            outputs.push(shared_output(&mut instances, output_config, &context).await?);
         }
 
-        // tag based outputs
-        for tag in input_config.tags.iter() {
-            if let Some(output_configs) = config.outputs.get(tag) {
-                for output_config in output_configs {
-                    outputs.push(Output::new(output_config.clone(), client.clone()));
+        // content based outputs
+        let mut routes = Vec::with_capacity(config.routes.len());
+        for route_config in config.routes.iter() {
+            let mut route = Route {
+                condition: Expression::parse(&route_config.condition)?,
+                outputs: Vec::new(),
+                exclusive: route_config.exclusive,
+            };
+
+            for output_config in config.route_outputs(route_config) {
+                if output_config.excludes(&name, &input_config.tags) {
+                    continue;
                 }
+
+                route
+                    .outputs
+                    .push(shared_output(&mut instances, output_config, &context).await?);
             }
+
+            routes.push(route);
         }
 
-        let input = Input::new(name.clone(), input_config, outputs, client.clone());
+        let input = Input::new(
+            name.clone(),
+            input_config,
+            outputs,
+            routes,
+            websub.as_ref(),
+            client.clone(),
+        )?;
 
         let rx = tx.subscribe();
 
@@ -122,6 +188,34 @@ fn watch_inputs(config: Config, client: Client) -> Result<Vec<JoinHandle<Result<
         }));
     }
 
+    // digests and held entries
+    for output in instances.into_values().filter(|output| output.has_tasks()) {
+        let rx = tx.subscribe();
+
+        tasks.push(tokio::spawn(async move {
+            if let Err(e) = output.run_tasks(rx).await {
+                error!("Output task stopped with an error: {}", &e);
+                return Err(e);
+            }
+
+            Ok(())
+        }));
+    }
+
+    // callbacks of the websub hubs
+    if let Some(websub) = websub {
+        let rx = tx.subscribe();
+
+        tasks.push(tokio::spawn(async move {
+            if let Err(e) = websub.serve(rx).await {
+                error!("WebSub server stopped with an error: {}", &e);
+                return Err(e);
+            }
+
+            Ok(())
+        }));
+    }
+
     tokio::spawn(async move {
         let mut sig_int = signal(SignalKind::interrupt()).unwrap();
         let mut sig_term = signal(SignalKind::terminate()).unwrap();
@@ -0,0 +1,92 @@
+use std::{io::ErrorKind, path::Path, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs, sync::Mutex};
+
+use super::Entry;
+use crate::{error::Error, Result};
+
+/// Entries waiting to be pushed, saved to a file on every change to survive
+/// restarts.
+pub struct Buffer {
+    path: PathBuf,
+    entries: Mutex<Vec<Buffered>>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Buffered {
+    pub input: String,
+    pub entry: Entry,
+}
+
+impl Buffer {
+    /// Loads the buffer `{kind}-{hex encoded id}.json` of an output from `state_dir`.
+    pub async fn load(kind: &str, id: &str, state_dir: &Path) -> Result<Self> {
+        // ids differing only in punctuation keep files of their own
+        let path = state_dir.join(format!("{}-{}.json", kind, hex::encode(id)));
+
+        let entries = match fs::read(&path).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| {
+                Error::Config(format!("{}: invalid state {}: {}", kind, path.display(), e))
+            })?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub async fn add(&self, input: &str, entries: Vec<Entry>) -> Result<()> {
+        let mut buffered = self.entries.lock().await;
+
+        buffered.extend(entries.into_iter().map(|entry| Buffered {
+            input: input.to_owned(),
+            entry,
+        }));
+
+        self.save(&buffered).await
+    }
+
+    /// The buffered entries of every input, in input order.
+    pub async fn by_input(&self) -> Vec<(String, Vec<Entry>)> {
+        let mut pending = self.entries.lock().await.clone();
+        pending.sort_by(|a, b| a.input.cmp(&b.input));
+
+        let mut groups: Vec<(String, Vec<Entry>)> = Vec::new();
+        for buffered in pending {
+            match groups.last_mut() {
+                Some((input, entries)) if *input == buffered.input => entries.push(buffered.entry),
+                _ => groups.push((buffered.input, vec![buffered.entry])),
+            }
+        }
+
+        groups
+    }
+
+    /// Removes the `count` entries pushed, keeping the ones added since.
+    pub async fn remove(&self, count: usize) -> Result<()> {
+        let mut buffered = self.entries.lock().await;
+
+        let count = count.min(buffered.len());
+        buffered.drain(..count);
+
+        self.save(&buffered).await
+    }
+
+    async fn save(&self, buffered: &[Buffered]) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+
+        // a write cut short leaves the previous state instead of truncated
+        // json failing every start
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(buffered).unwrap()).await?;
+        fs::rename(&tmp, &self.path).await?;
+
+        Ok(())
+    }
+}
@@ -1,10 +1,10 @@
-use std::process::Stdio;
+use std::{process::Stdio, sync::Arc};
 
 use log::debug;
 
 use tokio::{io::AsyncWriteExt, process::Command};
 
-use super::{Entry, OutputTrait};
+use super::{rate_limit::RateLimiter, Entry, OutputTrait};
 use crate::Result;
 use async_trait::async_trait;
 
@@ -12,14 +12,21 @@ pub struct Custom {
     command: String,
     arguments: Vec<String>,
     use_stdin: bool,
+    rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl Custom {
-    pub fn new(command: String, arguments: Vec<String>, use_stdin: bool) -> Self {
+    pub fn new(
+        command: String,
+        arguments: Vec<String>,
+        use_stdin: bool,
+        rate_limiter: Option<Arc<RateLimiter>>,
+    ) -> Self {
         Self {
             command,
             arguments,
             use_stdin,
+            rate_limiter,
         }
     }
 }
@@ -32,6 +39,10 @@ impl OutputTrait for Custom {
         for entry in entries {
             let serialized_message = serde_json::to_string(entry).unwrap();
 
+            if let Some(rate_limiter) = self.rate_limiter.as_ref() {
+                rate_limiter.acquire().await;
+            }
+
             if self.use_stdin {
                 debug!("pushing to custom stdin: {}", &self.command);
 
@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use reqwest::Url;
+
+use super::Entry;
+use crate::config::{DedupeConfig, DedupeKey};
+
+/// Query parameters only used to track where a visitor came from.
+const TRACKING_PARAMS: &[&str] = &[
+    "fbclid", "gclid", "dclid", "msclkid", "yclid", "igshid", "mc_cid", "mc_eid", "ref", "ref_src",
+    "_hsenc", "_hsmi",
+];
+
+/// Remembers the stories pushed to an output to skip the same story coming
+/// again, from another feed or the same one.
+pub struct Dedupe {
+    window: Duration,
+    keys: Vec<DedupeKey>,
+    seen: Mutex<HashMap<String, Instant>>,
+    /// a copy pushed at the same time waits for the keys of the first one
+    pub pushing: tokio::sync::Mutex<()>,
+}
+
+impl Dedupe {
+    pub fn new(config: DedupeConfig) -> Self {
+        Self {
+            window: config.window,
+            keys: config.by,
+            seen: Mutex::new(HashMap::new()),
+            pushing: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Keeps the entries not seen within the window, returns the keys to
+    /// mark once the entries are delivered.
+    pub fn retain(&self, entries: &mut Vec<Entry>) -> Vec<String> {
+        let now = Instant::now();
+
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, &mut at| now.duration_since(at) < self.window);
+
+        let mut kept: Vec<String> = Vec::new();
+
+        entries.retain(|entry| {
+            // the summary of a feed's overflow has the same url and title on
+            // every poll but stands for different entries
+            if entry.overflow {
+                return true;
+            }
+
+            let keys: Vec<String> = self
+                .keys
+                .iter()
+                .filter_map(|key| match key {
+                    DedupeKey::Url => canonical_url(&entry.url).map(|url| format!("url:{}", url)),
+                    DedupeKey::Title => {
+                        normalized_title(&entry.title).map(|title| format!("title:{}", title))
+                    }
+                })
+                .collect();
+
+            if keys
+                .iter()
+                .any(|key| seen.contains_key(key) || kept.contains(key))
+            {
+                return false;
+            }
+
+            kept.extend(keys);
+
+            true
+        });
+
+        kept
+    }
+
+    /// Marks the keys of delivered entries as seen.
+    pub fn mark(&self, keys: Vec<String>) {
+        let now = Instant::now();
+
+        let mut seen = self.seen.lock().unwrap();
+        for key in keys {
+            seen.insert(key, now);
+        }
+    }
+}
+
+/// The url without scheme, `www.`, fragment, tracking parameters and
+/// trailing slash.
+fn canonical_url(url: &str) -> Option<String> {
+    let mut url = Url::parse(url).ok()?;
+
+    url.set_fragment(None);
+
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| !name.starts_with("utm_") && !TRACKING_PARAMS.contains(&name.as_ref()))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+
+    if query.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+
+    let host = url.host_str()?;
+    let host = host.strip_prefix("www.").unwrap_or(host);
+
+    let mut canonical = format!("{}{}", host, url.path().trim_end_matches('/'));
+    if let Some(query) = url.query() {
+        canonical.push('?');
+        canonical.push_str(query);
+    }
+
+    Some(canonical)
+}
+
+/// The lowercase words of the title.
+fn normalized_title(title: &str) -> Option<String> {
+    let title = title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    Some(title).filter(|title| !title.is_empty())
+}
@@ -0,0 +1,422 @@
+use html2text::render::text_renderer::{RichAnnotation, TaggedLine, TextDecorator};
+
+use crate::config::{DescriptionConfig, DescriptionSource, Markup, TruncateMode};
+
+const DEFAULT_MAX_LENGTH: usize = 256;
+const DEFAULT_WRAP_WIDTH: usize = 80;
+
+/// Summary or content of a feed entry as found in the feed.
+#[derive(Clone)]
+pub struct RawText {
+    pub body: String,
+    pub html: bool,
+}
+
+/// A piece of text sharing the same annotations.
+struct Segment {
+    text: String,
+    annotations: Vec<RichAnnotation>,
+}
+
+/// Builds the description of an entry from its summary or content, at most
+/// `limit` characters long with the markup.
+pub fn describe(
+    summary: Option<&RawText>,
+    content: Option<&RawText>,
+    config: &DescriptionConfig,
+    markup: Markup,
+    limit: Option<usize>,
+) -> String {
+    let raw = match config.source.unwrap_or_default() {
+        DescriptionSource::Summary => summary.or(content),
+        DescriptionSource::Content => content.or(summary),
+    };
+
+    let lines = match raw {
+        Some(RawText { body, html: true }) => {
+            from_html(body, config.wrap_width.unwrap_or(DEFAULT_WRAP_WIDTH))
+        }
+        Some(RawText { body, html: false }) => body
+            .trim_end()
+            .lines()
+            .map(|line| {
+                vec![Segment {
+                    text: line.to_owned(),
+                    annotations: Vec::new(),
+                }]
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let visible = lines
+        .iter()
+        .map(|line| line.iter().map(|s| s.text.as_str()).collect::<String>())
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let mut max_length = match config.max_length.unwrap_or(DEFAULT_MAX_LENGTH) {
+        0 => usize::MAX,
+        max_length => max_length.min(limit.unwrap_or(usize::MAX)),
+    };
+
+    // the markup makes the text longer, cutting the text before rendering
+    // keeps links and escapes whole
+    loop {
+        let cut = cut(&visible, max_length, config.truncate.unwrap_or_default());
+        let text = render(&lines, cut, markup, config.link_footnotes.unwrap_or(true));
+
+        let length = text.chars().count();
+        match limit {
+            Some(limit) if length > limit && max_length > 0 => {
+                max_length = max_length
+                    .min(visible.chars().count())
+                    .saturating_sub(length - limit);
+            }
+            _ => return text,
+        }
+    }
+}
+
+fn from_html(html: &str, wrap_width: usize) -> Vec<Vec<Segment>> {
+    // 0 disables wrapping, html2text allocates rows of this width for tables
+    let width = match wrap_width {
+        0 => html.len().max(DEFAULT_WRAP_WIDTH),
+        width => width,
+    };
+
+    let lines = html2text::parse(html.as_bytes())
+        .render(width, Decorator)
+        .into_lines();
+
+    let mut lines: Vec<Vec<Segment>> = lines.iter().map(segments).collect();
+
+    while lines.last().is_some_and(|line| is_blank(line)) {
+        lines.pop();
+    }
+
+    lines
+}
+
+/// Text of an html document without any markup or wrapping.
+pub fn html_to_text(html: &str) -> String {
+    from_html(html, 0)
+        .iter()
+        .map(|line| line.iter().map(|s| s.text.as_str()).collect::<String>())
+        .collect::<Vec<String>>()
+        .join("\n")
+        .trim_end()
+        .to_owned()
+}
+
+/// Merges neighbouring strings with the same annotations.
+fn segments(line: &TaggedLine<Vec<RichAnnotation>>) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+
+    for ts in line.tagged_strings() {
+        match segments.last_mut() {
+            Some(segment) if segment.annotations == ts.tag => segment.text.push_str(&ts.s),
+            _ => segments.push(Segment {
+                text: ts.s.clone(),
+                annotations: ts.tag.clone(),
+            }),
+        }
+    }
+
+    segments
+}
+
+fn is_blank(line: &[Segment]) -> bool {
+    line.iter().all(|s| s.text.trim().is_empty())
+}
+
+/// Returns how many characters of `text` fit into `max_length` and whether
+/// the cut has to be marked with an ellipsis.
+fn cut(text: &str, max_length: usize, mode: TruncateMode) -> Option<(usize, bool)> {
+    if text.chars().count() <= max_length {
+        return None;
+    }
+
+    let cut: String = text.chars().take(max_length.saturating_sub(1)).collect();
+
+    let end = match mode {
+        TruncateMode::Char => None,
+        TruncateMode::Word => word_end(&cut),
+        TruncateMode::Sentence => {
+            if let Some(end) = sentence_end(&cut) {
+                return Some((cut[..end].chars().count(), false));
+            }
+            word_end(&cut)
+        }
+    };
+
+    let end = end.map_or(cut.len(), |end| cut[..end].trim_end().len());
+
+    Some((cut[..end].chars().count(), true))
+}
+
+fn word_end(text: &str) -> Option<usize> {
+    text.rfind(char::is_whitespace).filter(|&i| i > 0)
+}
+
+fn sentence_end(text: &str) -> Option<usize> {
+    text.char_indices()
+        .zip(text.chars().skip(1))
+        .filter(|((_, c), next)| matches!(c, '.' | '!' | '?') && next.is_whitespace())
+        .map(|((i, c), _)| i + c.len_utf8())
+        .last()
+}
+
+/// Renders the lines in the given markup, keeping only the first `cut`
+/// characters of the text.
+fn render(
+    lines: &[Vec<Segment>],
+    cut: Option<(usize, bool)>,
+    markup: Markup,
+    link_footnotes: bool,
+) -> String {
+    let mut text = String::new();
+    let mut footnotes: Vec<&str> = Vec::new();
+    let mut left = cut.map_or(usize::MAX, |(keep, _)| keep);
+
+    'lines: for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            if left == 0 {
+                break;
+            }
+            text.push('\n');
+            left -= 1;
+        }
+
+        for (j, segment) in line.iter().enumerate() {
+            if left == 0 {
+                break 'lines;
+            }
+
+            let length = segment.text.chars().count();
+            let s: String = segment.text.chars().take(left).collect();
+            left = left.saturating_sub(length);
+
+            let url = link(&segment.annotations);
+            let link_ends = url.is_some()
+                && (left == 0 || line.get(j + 1).and_then(|next| link(&next.annotations)) != url);
+
+            text.push_str(&decorate(&s, &segment.annotations, markup));
+
+            if let Some(url) =
+                url.filter(|_| markup == Markup::Plain && link_footnotes && link_ends)
+            {
+                let n = footnotes.iter().position(|&l| l == url).unwrap_or_else(|| {
+                    footnotes.push(url);
+                    footnotes.len() - 1
+                });
+                text.push_str(&format!("[{}]", n + 1));
+            }
+        }
+    }
+
+    if cut.is_some_and(|(_, ellipsis)| ellipsis) {
+        text.push('…');
+    }
+
+    if !footnotes.is_empty() {
+        text.push('\n');
+        for (i, url) in footnotes.iter().enumerate() {
+            text.push_str(&format!("\n[{}]: {}", i + 1, url));
+        }
+    }
+
+    text
+}
+
+fn link(annotations: &[RichAnnotation]) -> Option<&str> {
+    annotations.iter().find_map(|a| match a {
+        RichAnnotation::Link(url) => Some(url.as_str()),
+        _ => None,
+    })
+}
+
+/// Escapes `s` and wraps it in the markup of its annotations.
+fn decorate(s: &str, annotations: &[RichAnnotation], markup: Markup) -> String {
+    if markup == Markup::Plain {
+        return s.to_owned();
+    }
+
+    // markers around whitespace aren't recognized
+    let core = s.trim();
+    if core.is_empty() {
+        return escape(s, markup);
+    }
+    let start = s.len() - s.trim_start().len();
+    let (lead, trail) = (&s[..start], &s[start + core.len()..]);
+
+    let is_code = annotations
+        .iter()
+        .any(|a| matches!(a, RichAnnotation::Code | RichAnnotation::Preformat(_)));
+
+    let mut text = if is_code {
+        code(core, markup)
+    } else {
+        escape(core, markup)
+    };
+
+    for annotation in annotations.iter().rev() {
+        let (open, close) = match (annotation, markup) {
+            (RichAnnotation::Strong, Markup::Markdown) => ("**", "**"),
+            (RichAnnotation::Strong, Markup::Slack) => ("*", "*"),
+            (RichAnnotation::Strong, Markup::TelegramHtml) => ("<b>", "</b>"),
+            (RichAnnotation::Emphasis, Markup::Markdown) => ("*", "*"),
+            (RichAnnotation::Emphasis, Markup::Slack) => ("_", "_"),
+            (RichAnnotation::Emphasis, Markup::TelegramHtml) => ("<i>", "</i>"),
+            (RichAnnotation::Strikeout, Markup::Markdown) => ("~~", "~~"),
+            (RichAnnotation::Strikeout, Markup::Slack) => ("~", "~"),
+            (RichAnnotation::Strikeout, Markup::TelegramHtml) => ("<s>", "</s>"),
+            (RichAnnotation::Link(url), _) => {
+                text = link_markup(&text, url, markup);
+                continue;
+            }
+            _ => continue,
+        };
+
+        text = format!("{}{}{}", open, text, close);
+    }
+
+    format!("{}{}{}", escape(lead, markup), text, escape(trail, markup))
+}
+
+/// Wraps `text`, already in the markup, in a link to `url`.
+pub fn link_markup(text: &str, url: &str, markup: Markup) -> String {
+    match markup {
+        Markup::Plain => text.to_owned(),
+        Markup::Markdown => format!(
+            "[{}]({})",
+            text,
+            url.replace(' ', "%20").replace(')', "%29")
+        ),
+        Markup::Slack => format!("<{}|{}>", url.replace('|', "%7C"), text),
+        Markup::TelegramHtml => format!("<a href=\"{}\">{}</a>", escape(url, markup), text),
+    }
+}
+
+fn code(s: &str, markup: Markup) -> String {
+    match markup {
+        Markup::Plain => s.to_owned(),
+        // neither has a way to escape a backtick inside code
+        Markup::Markdown => format!("`{}`", s.replace('`', "'")),
+        Markup::Slack => format!("`{}`", escape(&s.replace('`', "'"), markup)),
+        Markup::TelegramHtml => format!("<code>{}</code>", escape(s, markup)),
+    }
+}
+
+/// Escapes the characters that have a meaning in the markup.
+pub fn escape(s: &str, markup: Markup) -> String {
+    match markup {
+        Markup::Plain => s.to_owned(),
+        Markup::Markdown => {
+            let mut escaped = String::with_capacity(s.len());
+            for c in s.chars() {
+                if matches!(
+                    c,
+                    '\\' | '*' | '_' | '~' | '`' | '|' | '>' | '#' | '[' | ']' | '(' | ')'
+                ) {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            escaped
+        }
+        Markup::Slack => s
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;"),
+        Markup::TelegramHtml => s
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;"),
+    }
+}
+
+/// Keeps the annotations of html2text's rich decorator without adding any
+/// markers to the text.
+struct Decorator;
+
+impl TextDecorator for Decorator {
+    type Annotation = RichAnnotation;
+
+    fn decorate_link_start(&mut self, url: &str) -> (String, Self::Annotation) {
+        ("".to_owned(), RichAnnotation::Link(url.to_owned()))
+    }
+
+    fn decorate_link_end(&mut self) -> String {
+        "".to_owned()
+    }
+
+    fn decorate_em_start(&mut self) -> (String, Self::Annotation) {
+        ("".to_owned(), RichAnnotation::Emphasis)
+    }
+
+    fn decorate_em_end(&mut self) -> String {
+        "".to_owned()
+    }
+
+    fn decorate_strong_start(&mut self) -> (String, Self::Annotation) {
+        ("".to_owned(), RichAnnotation::Strong)
+    }
+
+    fn decorate_strong_end(&mut self) -> String {
+        "".to_owned()
+    }
+
+    fn decorate_strikeout_start(&mut self) -> (String, Self::Annotation) {
+        ("".to_owned(), RichAnnotation::Strikeout)
+    }
+
+    fn decorate_strikeout_end(&mut self) -> String {
+        "".to_owned()
+    }
+
+    fn decorate_code_start(&mut self) -> (String, Self::Annotation) {
+        ("".to_owned(), RichAnnotation::Code)
+    }
+
+    fn decorate_code_end(&mut self) -> String {
+        "".to_owned()
+    }
+
+    fn decorate_preformat_first(&mut self) -> Self::Annotation {
+        RichAnnotation::Preformat(false)
+    }
+
+    fn decorate_preformat_cont(&mut self) -> Self::Annotation {
+        RichAnnotation::Preformat(true)
+    }
+
+    fn decorate_image(&mut self, src: &str, title: &str) -> (String, Self::Annotation) {
+        (title.to_owned(), RichAnnotation::Image(src.to_owned()))
+    }
+
+    fn header_prefix(&mut self, _level: usize) -> String {
+        "".to_owned()
+    }
+
+    fn quote_prefix(&mut self) -> String {
+        "> ".to_owned()
+    }
+
+    fn unordered_item_prefix(&mut self) -> String {
+        "• ".to_owned()
+    }
+
+    fn ordered_item_prefix(&mut self, i: i64) -> String {
+        format!("{}. ", i)
+    }
+
+    fn make_subblock_decorator(&self) -> Self {
+        Decorator
+    }
+
+    fn finalise(&mut self, _links: Vec<String>) -> Vec<TaggedLine<Self::Annotation>> {
+        Vec::new()
+    }
+}
@@ -0,0 +1,64 @@
+use std::{path::Path, time::Duration};
+
+use chrono::{Days, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use super::buffer::Buffer;
+use crate::{config::DigestConfig, error::Error, Result};
+
+/// Buffers the entries of an output to push them together once per period.
+pub struct Digest {
+    every: Option<Duration>,
+    at: Option<NaiveTime>,
+    timezone: Tz,
+
+    pub buffer: Buffer,
+}
+
+impl Digest {
+    pub async fn new(config: DigestConfig, id: &str, state_dir: &Path) -> Result<Self> {
+        let at = match (config.every, config.at) {
+            (Some(_), None) => None,
+            (None, Some(at)) => Some(NaiveTime::parse_from_str(&at, "%H:%M").map_err(|_| {
+                Error::Config(format!("digest: invalid time, expected HH:MM: {}", at))
+            })?),
+            _ => {
+                return Err(Error::Config(
+                    "digest: exactly one of `every` or `at` has to be set".to_owned(),
+                ))
+            }
+        };
+
+        Ok(Self {
+            every: config.every,
+            at,
+            timezone: config.timezone,
+            buffer: Buffer::load("digest", id, state_dir).await?,
+        })
+    }
+
+    /// Time left until the end of the current period.
+    pub fn next(&self) -> Duration {
+        if let Some(every) = self.every {
+            return every;
+        }
+
+        let at = self.at.unwrap_or_default();
+        let now = Utc::now().with_timezone(&self.timezone);
+
+        let mut day = now.date_naive();
+        loop {
+            // a time skipped by a dst change falls on the next day
+            if let Some(next) = self
+                .timezone
+                .from_local_datetime(&day.and_time(at))
+                .earliest()
+                .filter(|next| *next > now)
+            {
+                return (next - now).to_std().unwrap_or_default();
+            }
+
+            day = day + Days::new(1);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Embed limits shared by the discord outputs:
+//! https://discord.com/developers/docs/resources/channel#embed-object-embed-limits
+
+use super::{batches, truncate, Entry};
+
+const MAX_EMBEDS: usize = 10;
+const MAX_EMBEDS_LENGTH: usize = 6000;
+
+const MAX_TITLE_LENGTH: usize = 256;
+pub const MAX_DESCRIPTION_LENGTH: usize = 4096;
+const MAX_AUTHOR_LENGTH: usize = 256;
+const MAX_FIELDS: usize = 25;
+const MAX_FIELD_NAME_LENGTH: usize = 256;
+const MAX_FIELD_VALUE_LENGTH: usize = 1024;
+
+pub const MAX_CONTENT_LENGTH: usize = 2000;
+pub const MAX_USERNAME_LENGTH: usize = 80;
+pub const MAX_THREAD_NAME_LENGTH: usize = 100;
+
+/// Entry texts cut down to the embed field limits.
+pub struct EmbedText<'a> {
+    pub entry: &'a Entry,
+    pub title: String,
+    pub description: String,
+    pub author: Option<String>,
+    /// name, value and inline flag of every field
+    pub fields: Vec<(String, String, bool)>,
+}
+
+impl<'a> EmbedText<'a> {
+    fn new(entry: &'a Entry) -> Self {
+        Self {
+            entry,
+            title: truncate(&entry.title, MAX_TITLE_LENGTH),
+            description: truncate(&entry.description, MAX_DESCRIPTION_LENGTH),
+            author: entry
+                .author
+                .as_ref()
+                .map(|author| truncate(author, MAX_AUTHOR_LENGTH)),
+            fields: entry
+                .fields
+                .iter()
+                .take(MAX_FIELDS)
+                .map(|field| {
+                    (
+                        truncate(&field.name, MAX_FIELD_NAME_LENGTH),
+                        truncate(&field.value, MAX_FIELD_VALUE_LENGTH),
+                        field.inline,
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.title.chars().count()
+            + self.description.chars().count()
+            + self.author.as_ref().map_or(0, |a| a.chars().count())
+            + self
+                .fields
+                .iter()
+                .map(|(name, value, _)| name.chars().count() + value.chars().count())
+                .sum::<usize>()
+    }
+}
+
+/// Splits entries into messages that stay within the embed count and total length limits.
+pub fn embed_batches(entries: &[Entry]) -> Vec<Vec<EmbedText<'_>>> {
+    batches(
+        entries.iter().map(EmbedText::new),
+        MAX_EMBEDS,
+        MAX_EMBEDS_LENGTH,
+        EmbedText::len,
+    )
+}
@@ -1,19 +1,47 @@
-use log::debug;
+use std::sync::Arc;
 
-use serenity::{builder::CreateEmbed, http::Http};
+use log::{debug, info};
 
-use super::{Entry, OutputTrait};
+use serenity::{
+    builder::CreateEmbed,
+    http::Http,
+    model::id::{ChannelId, UserId},
+};
+
+use super::{discord::embed_batches, rate_limit::RateLimiter, Entry, OutputTrait};
 use crate::Result;
 use async_trait::async_trait;
 
 pub struct DiscordBot {
-    token: String,
-    user_id: u64,
+    http: Http,
+    channel_id: ChannelId,
+    rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl DiscordBot {
-    pub fn new(token: String, user_id: u64) -> Self {
-        Self { token, user_id }
+    pub async fn new(
+        token: String,
+        user_id: u64,
+        rate_limiter: Option<Arc<RateLimiter>>,
+    ) -> Result<Self> {
+        // the client keeps serenity's ratelimiter state between pushes
+        let http = Http::new_with_token(&token);
+
+        // fails early on an invalid token instead of on the first entry
+        let bot = http.get_current_user().await?;
+
+        let channel = UserId(user_id).create_dm_channel(&http).await?;
+
+        info!(
+            "discord bot \"{}\" will push entries to dm channel {}",
+            bot.name, channel.id
+        );
+
+        Ok(Self {
+            http,
+            channel_id: channel.id,
+            rate_limiter,
+        })
     }
 }
 
@@ -22,34 +50,39 @@ impl OutputTrait for DiscordBot {
     async fn push(&self, _: &str, entries: &[Entry]) -> Result<()> {
         debug!("pushing {} entries to discord bot", entries.len());
 
-        let http = Http::new_with_token(&self.token);
-
-        let user = http.get_user(self.user_id).await?;
-
-        for chunk in entries.chunks(10) {
-            let embeds: Vec<CreateEmbed> = chunk
-                .iter()
-                .map(|entry| {
+        for batch in embed_batches(entries) {
+            let embeds: Vec<CreateEmbed> = batch
+                .into_iter()
+                .map(|text| {
                     let mut e = CreateEmbed::default();
-                    e.title(entry.title.clone());
-                    e.description(entry.description.clone());
-                    if let Some(author) = entry.author.as_ref() {
+                    e.title(text.title);
+                    e.description(text.description);
+                    if let Some(author) = text.author {
                         e.author(|a| {
-                            a.name(author.clone());
+                            a.name(author);
                             a
                         });
                     }
-                    e.url(entry.url.clone());
-                    e.timestamp(entry.timestamp.to_rfc3339());
+                    e.fields(text.fields);
+                    e.url(text.entry.url.clone());
+                    if let Some(image) = text.entry.image.as_ref() {
+                        e.thumbnail(image);
+                    }
+                    e.timestamp(text.entry.timestamp.to_rfc3339());
                     e
                 })
                 .collect();
 
-            user.dm(&http, |m| {
-                m.set_embeds(embeds);
-                m
-            })
-            .await?;
+            if let Some(rate_limiter) = self.rate_limiter.as_ref() {
+                rate_limiter.acquire().await;
+            }
+
+            self.channel_id
+                .send_message(&self.http, |m| {
+                    m.set_embeds(embeds);
+                    m
+                })
+                .await?;
         }
 
         Ok(())
@@ -1,52 +1,184 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
 use chrono::{DateTime, Utc};
-use log::debug;
-use reqwest::Client;
+use log::{debug, warn};
+use reqwest::{Client, StatusCode};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use super::{Entry, OutputTrait};
-use crate::Result;
+use super::{
+    discord::{embed_batches, MAX_CONTENT_LENGTH, MAX_THREAD_NAME_LENGTH, MAX_USERNAME_LENGTH},
+    rate_limit::RateLimiter,
+    truncate, Entry, OutputTrait,
+};
+use crate::{config::DiscordWebhookConfig, Result};
 use async_trait::async_trait;
 
+const RATE_LIMIT_RETRIES: usize = 5;
+
 pub struct DiscordWebhook {
     url: String,
     client: Client,
+    rate_limiter: Option<Arc<RateLimiter>>,
+
+    username: Option<String>,
+    avatar_url: Option<String>,
+    use_feed_icon: bool,
+
+    thread_id: Option<u64>,
+    thread_name: Option<String>,
+
+    content: Option<String>,
+    role_mentions: Vec<u64>,
+
+    color: Option<u32>,
+    colors: HashMap<String, u32>,
 }
 
 impl DiscordWebhook {
-    pub fn new(url: String, client: Client) -> Self {
-        Self { url, client }
+    pub fn new(
+        config: DiscordWebhookConfig,
+        client: Client,
+        rate_limiter: Option<Arc<RateLimiter>>,
+    ) -> Self {
+        Self {
+            url: config.url,
+            client,
+            rate_limiter,
+            username: config
+                .username
+                .map(|username| truncate(&username, MAX_USERNAME_LENGTH)),
+            avatar_url: config.avatar_url,
+            use_feed_icon: config.use_feed_icon,
+            thread_id: config.thread_id,
+            thread_name: config
+                .thread_name
+                .map(|thread_name| truncate(&thread_name, MAX_THREAD_NAME_LENGTH)),
+            content: config.content,
+            role_mentions: config.role_mentions,
+            color: config.color,
+            colors: config.colors,
+        }
+    }
+
+    fn content(&self) -> Option<String> {
+        let mentions = self
+            .role_mentions
+            .iter()
+            .map(|id| format!("<@&{}>", id))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        let content = match (mentions.is_empty(), self.content.as_ref()) {
+            (true, None) => None,
+            (true, Some(content)) => Some(content.clone()),
+            (false, None) => Some(mentions),
+            (false, Some(content)) => Some(format!("{} {}", mentions, content)),
+        };
+
+        content.map(|content| truncate(&content, MAX_CONTENT_LENGTH))
+    }
+
+    async fn send(&self, message: &Message, thread_id: Option<u64>) -> Result<SentMessage> {
+        let mut retries = 0;
+
+        loop {
+            if let Some(rate_limiter) = self.rate_limiter.as_ref() {
+                rate_limiter.acquire().await;
+            }
+
+            let mut req = self.client.post(&self.url).query(&[("wait", "true")]);
+            if let Some(thread_id) = thread_id {
+                req = req.query(&[("thread_id", thread_id)]);
+            }
+
+            let res = req.json(message).send().await?;
+
+            if res.status() != StatusCode::TOO_MANY_REQUESTS || retries == RATE_LIMIT_RETRIES {
+                let sent = res.error_for_status()?.json().await?;
+                return Ok(sent);
+            }
+
+            let rate_limit: RateLimit = res.json().await?;
+            let wait = Duration::from_secs_f64(rate_limit.retry_after.max(0.0));
+
+            warn!(
+                "discord webhook rate limited{}, retrying in {:?}",
+                if rate_limit.global { " globally" } else { "" },
+                wait
+            );
+
+            tokio::time::sleep(wait).await;
+            retries += 1;
+        }
     }
 }
 
 #[async_trait]
 impl OutputTrait for DiscordWebhook {
-    async fn push(&self, _: &str, entries: &[Entry]) -> Result<()> {
+    async fn push(&self, name: &str, entries: &[Entry]) -> Result<()> {
         debug!("pushing {} entries to discord webhook", entries.len());
 
-        for chunk in entries.chunks(10) {
-            let embeds: Vec<EmbedObject> = chunk
-                .iter()
-                .map(|entry| EmbedObject {
-                    title: entry.title.clone(),
-                    description: entry.description.clone(),
-                    author: entry.author.as_ref().map(|name| EmbedAuthor {
-                        name: name.clone(),
-                        url: None,
-                    }),
-                    url: entry.url.clone(),
-                    timestamp: entry.timestamp,
+        let color = self.colors.get(name).copied().or(self.color);
+
+        // mentions are only sent with the first message to avoid repeated pings
+        let mut content = self.content();
+
+        // a forum post is created by the first message, the rest is posted into it
+        let mut thread_id = self.thread_id;
+        let mut thread_name = self.thread_name.clone().filter(|_| thread_id.is_none());
+
+        for batch in embed_batches(entries) {
+            let avatar_url = if self.use_feed_icon {
+                batch
+                    .iter()
+                    .find_map(|text| text.entry.feed_icon.clone())
+                    .or_else(|| self.avatar_url.clone())
+            } else {
+                self.avatar_url.clone()
+            };
+
+            let embeds: Vec<EmbedObject> = batch
+                .into_iter()
+                .map(|text| EmbedObject {
+                    title: text.title,
+                    description: text.description,
+                    author: text.author.map(|name| EmbedAuthor { name, url: None }),
+                    fields: text
+                        .fields
+                        .into_iter()
+                        .map(|(name, value, inline)| EmbedField {
+                            name,
+                            value,
+                            inline,
+                        })
+                        .collect(),
+                    url: text.entry.url.clone(),
+                    thumbnail: text.entry.image.clone().map(|url| EmbedThumbnail { url }),
+                    timestamp: text.entry.timestamp,
+                    color,
                 })
                 .collect();
 
-            let message = Message { embeds };
+            let allowed_mentions = content.as_ref().map(|_| AllowedMentions {
+                parse: Vec::new(),
+                roles: self.role_mentions.clone(),
+            });
+
+            let message = Message {
+                content: content.take(),
+                username: self.username.clone(),
+                avatar_url,
+                thread_name: thread_name.take(),
+                allowed_mentions,
+                embeds,
+            };
+
+            let sent = self.send(&message, thread_id).await?;
 
-            self.client
-                .post(&self.url)
-                .json(&message)
-                .send()
-                .await?
-                .error_for_status()?;
+            if message.thread_name.is_some() {
+                thread_id = sent.channel_id.parse().ok();
+            }
         }
 
         Ok(())
@@ -55,16 +187,38 @@ impl OutputTrait for DiscordWebhook {
 
 #[derive(Serialize)]
 struct Message {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avatar_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed_mentions: Option<AllowedMentions>,
     embeds: Vec<EmbedObject>,
 }
 
+#[derive(Serialize)]
+struct AllowedMentions {
+    parse: Vec<String>,
+    roles: Vec<u64>,
+}
+
 #[derive(Serialize)]
 struct EmbedObject {
     title: String,
     description: String,
     author: Option<EmbedAuthor>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<EmbedField>,
     url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thumbnail: Option<EmbedThumbnail>,
     timestamp: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<u32>,
 }
 
 #[derive(Serialize)]
@@ -72,3 +226,27 @@ struct EmbedAuthor {
     name: String,
     url: Option<String>,
 }
+
+#[derive(Serialize)]
+struct EmbedThumbnail {
+    url: String,
+}
+
+#[derive(Serialize)]
+struct EmbedField {
+    name: String,
+    value: String,
+    inline: bool,
+}
+
+#[derive(Deserialize)]
+struct RateLimit {
+    retry_after: f64,
+    #[serde(default)]
+    global: bool,
+}
+
+#[derive(Deserialize)]
+struct SentMessage {
+    channel_id: String,
+}
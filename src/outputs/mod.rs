@@ -1,43 +1,374 @@
+mod buffer;
 pub mod custom;
+mod dedupe;
+mod description;
+mod digest;
+mod discord;
 pub mod discord_bot;
 pub mod discord_webhook;
+mod rate_limit;
+mod schedule;
 pub mod slack;
+mod template;
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
+use feed_rs::model::Feed;
+use log::debug;
 use reqwest::Client;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::broadcast::Receiver;
 
-use crate::{config::OutputConfig, Result};
+use crate::{
+    config::{
+        DedupeConfig, DescriptionConfig, Markup, OutputConfig, OutputKind, OutsideWindow,
+        RateLimitConfig,
+    },
+    filter::Filters,
+    Result,
+};
 
 use self::{
-    custom::Custom, discord_bot::DiscordBot, discord_webhook::DiscordWebhook, slack::Slack,
+    custom::Custom,
+    dedupe::Dedupe,
+    description::{describe, RawText},
+    digest::Digest,
+    discord_bot::DiscordBot,
+    discord_webhook::DiscordWebhook,
+    rate_limit::RateLimiter,
+    schedule::Schedule,
+    slack::Slack,
+    template::Template,
 };
 
+/// State shared by all the outputs.
+pub struct OutputContext {
+    pub client: Client,
+    pub dedupe: Option<DedupeConfig>,
+    pub state_dir: PathBuf,
+    /// rate limiters by destination
+    limiters: Mutex<HashMap<String, Arc<RateLimiter>>>,
+}
+
+impl OutputContext {
+    pub fn new(client: Client, dedupe: Option<DedupeConfig>, state_dir: PathBuf) -> Self {
+        Self {
+            client,
+            dedupe,
+            state_dir,
+            limiters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The limiter of a destination, created with the settings of the first
+    /// output pushing to it.
+    fn limiter(&self, destination: String, config: RateLimitConfig) -> Arc<RateLimiter> {
+        self.limiters
+            .lock()
+            .unwrap()
+            .entry(destination)
+            .or_insert_with(|| Arc::new(RateLimiter::new(config)))
+            .clone()
+    }
+}
+
 pub struct Output {
     output: Box<dyn OutputTrait + Send + Sync>,
+    filters: Filters,
+    dedupe: Option<Dedupe>,
+    digest: Option<Digest>,
+    schedule: Option<Schedule>,
+    template: Option<Template>,
+    description: Option<DescriptionConfig>,
+    markup: Markup,
+    /// longest description the platform shows
+    description_limit: Option<usize>,
 }
 
 impl Output {
-    pub fn new(output_config: OutputConfig, client: Client) -> Self {
-        let output: Box<dyn OutputTrait + Send + Sync> = match output_config {
-            OutputConfig::Custom {
+    pub async fn new(output_config: OutputConfig, context: &OutputContext) -> Result<Self> {
+        let client = context.client.clone();
+        let state_dir = context.state_dir.as_path();
+        let markup = output_config.markup.unwrap_or(match output_config.kind {
+            OutputKind::Custom { .. } => Markup::Plain,
+            OutputKind::DiscordBot { .. } | OutputKind::DiscordWebhook(_) => Markup::Markdown,
+            OutputKind::Slack(_) => Markup::Slack,
+        });
+
+        let description_limit = match output_config.kind {
+            OutputKind::Custom { .. } => None,
+            OutputKind::DiscordBot { .. } | OutputKind::DiscordWebhook(_) => {
+                Some(discord::MAX_DESCRIPTION_LENGTH)
+            }
+            OutputKind::Slack(_) => Some(slack::MAX_TEXT_LENGTH),
+        };
+
+        // roughly the documented limits of the platforms
+        let (destination, default_rate_limit) = match &output_config.kind {
+            OutputKind::Custom { .. } => (format!("custom:{}", output_config.id), None),
+            OutputKind::DiscordBot { token, user_id } => (
+                format!("discord_bot:{}:{}", token, user_id),
+                Some((5, Duration::from_secs(5))),
+            ),
+            OutputKind::DiscordWebhook(config) => (
+                format!("discord_webhook:{}", config.url),
+                Some((30, Duration::from_secs(60))),
+            ),
+            OutputKind::Slack(config) => (
+                format!(
+                    "slack:{}",
+                    config
+                        .url
+                        .iter()
+                        .chain(config.token.iter())
+                        .chain(config.channel.iter())
+                        .map(String::as_str)
+                        .collect::<Vec<&str>>()
+                        .join(":")
+                ),
+                Some((1, Duration::from_secs(1))),
+            ),
+        };
+
+        let rate_limiter = output_config
+            .rate_limit
+            .or(default_rate_limit.map(|(messages, per)| RateLimitConfig { messages, per }))
+            .filter(|config| config.messages > 0)
+            .map(|config| context.limiter(destination, config));
+
+        let output: Box<dyn OutputTrait + Send + Sync> = match output_config.kind {
+            OutputKind::Custom {
                 command,
                 arguments,
                 use_stdin,
-            } => Box::new(Custom::new(command, arguments, use_stdin)),
-            OutputConfig::DiscordWebhook { url } => Box::new(DiscordWebhook::new(url, client)),
-            OutputConfig::DiscordBot { token, user_id } => {
-                Box::new(DiscordBot::new(token, user_id))
+            } => Box::new(Custom::new(command, arguments, use_stdin, rate_limiter)),
+            OutputKind::DiscordWebhook(config) => {
+                Box::new(DiscordWebhook::new(config, client, rate_limiter))
+            }
+            OutputKind::DiscordBot { token, user_id } => {
+                Box::new(DiscordBot::new(token, user_id, rate_limiter).await?)
             }
-            OutputConfig::Slack { url } => Box::new(Slack::new(url, client)),
+            OutputKind::Slack(config) => Box::new(Slack::new(config, client, rate_limiter).await?),
+        };
+
+        let filters = Filters::new(output_config.filters)?;
+
+        let dedupe = context
+            .dedupe
+            .clone()
+            .filter(|_| output_config.dedupe)
+            .map(Dedupe::new);
+
+        let digest = match output_config.digest {
+            Some(config) => Some(Digest::new(config, &output_config.id, state_dir).await?),
+            None => None,
         };
 
-        Self { output }
+        let schedule = match output_config.schedule {
+            Some(config) => Some(Schedule::new(config, &output_config.id, state_dir).await?),
+            None => None,
+        };
+
+        let template = match output_config.template {
+            Some(config) => Some(Template::new(config).await?),
+            None => None,
+        };
+
+        Ok(Self {
+            output,
+            filters,
+            dedupe,
+            digest,
+            schedule,
+            template,
+            description: output_config.description,
+            markup,
+            description_limit,
+        })
     }
 
     pub async fn push(&self, name: &str, entries: &[Entry]) -> Result<()> {
-        self.output.push(name, entries).await
+        // entries come with a plain description built with the input's settings
+        let describe = self.description.is_some() || self.markup != Markup::Plain;
+
+        if self.filters.is_empty()
+            && self.dedupe.is_none()
+            && self.digest.is_none()
+            && self.schedule.is_none()
+            && self.template.is_none()
+            && !describe
+        {
+            return self.output.push(name, entries).await;
+        }
+
+        let mut entries: Vec<Entry> = entries
+            .iter()
+            .filter(|entry| self.filters.matches(name, entry))
+            .cloned()
+            .collect();
+
+        let _pushing = match self.dedupe.as_ref() {
+            Some(dedupe) => Some(dedupe.pushing.lock().await),
+            None => None,
+        };
+
+        let keys = match self.dedupe.as_ref() {
+            Some(dedupe) => dedupe.retain(&mut entries),
+            None => Vec::new(),
+        };
+
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        if describe {
+            let config = self.description.unwrap_or_default();
+            entries = entries
+                .iter()
+                .map(|entry| entry.describe(config, self.markup, self.description_limit))
+                .collect();
+        }
+
+        if let Some(template) = self.template.as_ref() {
+            entries = entries
+                .iter()
+                .map(|entry| template.render(name, entry))
+                .collect::<Result<Vec<Entry>>>()?;
+        }
+
+        let delivered = match self.digest.as_ref() {
+            Some(digest) => digest.buffer.add(name, entries).await.map(|_| true)?,
+            None => self.deliver(name, entries).await?,
+        };
+
+        // dropped entries don't hide later copies
+        if let (Some(dedupe), true) = (self.dedupe.as_ref(), delivered) {
+            dedupe.mark(keys);
+        }
+
+        Ok(())
+    }
+
+    /// Pushes the entries if a delivery window is open, returns whether they
+    /// were pushed or held.
+    async fn deliver(&self, name: &str, entries: Vec<Entry>) -> Result<bool> {
+        if let Some(schedule) = self.schedule.as_ref() {
+            if !schedule.is_open() {
+                return match schedule.outside {
+                    OutsideWindow::Hold => schedule.held.add(name, entries).await.map(|_| true),
+                    OutsideWindow::Drop => {
+                        debug!("dropping {} entries outside of the schedule", entries.len());
+                        Ok(false)
+                    }
+                };
+            }
+
+            self.release_held(schedule).await?;
+        }
+
+        self.output.push(name, &entries).await?;
+
+        Ok(true)
+    }
+
+    async fn release_held(&self, schedule: &Schedule) -> Result<()> {
+        let _releasing = schedule.releasing.lock().await;
+
+        let pending = schedule.held.by_input().await;
+
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let count = pending.iter().map(|(_, entries)| entries.len()).sum();
+        debug!("pushing {} held entries", count);
+
+        for (input, entries) in pending.iter() {
+            self.output.push(input, entries).await?;
+        }
+
+        schedule.held.remove(count).await
+    }
+
+    pub fn has_tasks(&self) -> bool {
+        self.digest.is_some() || self.schedule.is_some()
+    }
+
+    /// Runs the digests and the release of held entries.
+    pub async fn run_tasks(&self, kill: Receiver<()>) -> Result<()> {
+        tokio::try_join!(
+            self.push_digests(kill.resubscribe()),
+            self.release_on_open(kill)
+        )?;
+
+        Ok(())
+    }
+
+    /// Pushes the held entries whenever a delivery window opens.
+    async fn release_on_open(&self, mut kill: Receiver<()>) -> Result<()> {
+        let schedule = match self.schedule.as_ref() {
+            Some(schedule) => schedule,
+            None => return Ok(()),
+        };
+
+        // entries held before a restart within a window
+        if schedule.is_open() {
+            self.release_held(schedule).await?;
+        }
+
+        loop {
+            tokio::select! {
+                biased;
+                _ = kill.recv() => break,
+                _ = tokio::time::sleep(schedule.until_open()) => {},
+            };
+
+            if schedule.is_open() {
+                self.release_held(schedule).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pushes the buffered entries at the end of every digest period.
+    async fn push_digests(&self, mut kill: Receiver<()>) -> Result<()> {
+        let digest = match self.digest.as_ref() {
+            Some(digest) => digest,
+            None => return Ok(()),
+        };
+
+        loop {
+            tokio::select! {
+                biased;
+                _ = kill.recv() => break,
+                _ = tokio::time::sleep(digest.next()) => {},
+            };
+
+            let pending = digest.buffer.by_input().await;
+
+            if pending.is_empty() {
+                continue;
+            }
+
+            let count = pending.iter().map(|(_, entries)| entries.len()).sum();
+            debug!("pushing a digest of {} entries", count);
+
+            // one push per input keeps its colors, threads and name
+            for (input, entries) in pending {
+                self.deliver(&input, entries).await?;
+            }
+
+            digest.buffer.remove(count).await?;
+        }
+
+        Ok(())
     }
 }
 
@@ -46,50 +377,163 @@ trait OutputTrait {
     async fn push(&self, name: &str, entries: &[Entry]) -> Result<()>;
 }
 
-const TEXT_WIDTH: usize = 80;
-const DESCRIPTION_MAX_LENGTH: usize = 256;
+/// Shortens `text` to at most `max` characters, marking the cut with an ellipsis.
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_owned();
+    }
+
+    let mut truncated: String = text.chars().take(max.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Groups `items` into batches of at most `max_count` items whose summed `size`
+/// doesn't exceed `max_size`. An item bigger than `max_size` gets a batch of its own.
+fn batches<T>(
+    items: impl IntoIterator<Item = T>,
+    max_count: usize,
+    max_size: usize,
+    size: impl Fn(&T) -> usize,
+) -> Vec<Vec<T>> {
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut batch_size = 0;
+
+    for item in items {
+        let item_size = size(&item);
 
-#[derive(Serialize)]
+        if !batch.is_empty() && (batch.len() == max_count || batch_size + item_size > max_size) {
+            batches.push(std::mem::take(&mut batch));
+            batch_size = 0;
+        }
+
+        batch_size += item_size;
+        batch.push(item);
+    }
+
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+
+    batches
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Entry {
-    title: String,
-    description: String,
-    author: Option<String>,
+    pub id: String,
+    pub title: String,
+    pub description: String,
+    pub author: Option<String>,
+    pub url: String,
+    pub timestamp: DateTime<Utc>,
+    pub updated: Option<DateTime<Utc>>,
+    pub categories: Vec<String>,
+    pub links: Vec<String>,
+    pub enclosures: Vec<Enclosure>,
+    /// thumbnail or first image enclosure
+    pub image: Option<String>,
+    pub feed_title: Option<String>,
+    pub feed_icon: Option<String>,
+    pub tags: Vec<String>,
+    /// extra fields rendered from the output's template
+    pub fields: Vec<Field>,
+
+    #[serde(skip)]
+    summary: Option<RawText>,
+    #[serde(skip)]
+    content: Option<RawText>,
+    /// description settings of the input
+    #[serde(skip)]
+    description_config: DescriptionConfig,
+    /// stands for entries left out of a push
+    #[serde(skip)]
+    overflow: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Enclosure {
     url: String,
-    timestamp: DateTime<Utc>,
+    media_type: Option<String>,
+    length: Option<u64>,
 }
 
-impl From<feed_rs::model::Entry> for Entry {
-    fn from(entry: feed_rs::model::Entry) -> Self {
-        let description = if let Some(summary) = entry.summary {
-            match summary.content_type.subtype().as_str() {
-                "html" => html2text::from_read(summary.content.as_bytes(), TEXT_WIDTH),
-                _ => summary.content,
-            }
-        } else if let Some(content) = entry.content {
-            match content.content_type.subtype().as_str() {
-                "html" => content.body.map_or_else(
-                    || "".to_owned(),
-                    |body| html2text::from_read(body.as_bytes(), TEXT_WIDTH),
-                ),
-                _ => content.body.unwrap_or_else(|| "".to_owned()),
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Field {
+    name: String,
+    value: String,
+    inline: bool,
+}
+
+impl Entry {
+    pub fn new(
+        entry: feed_rs::model::Entry,
+        feed: &Feed,
+        tags: &[String],
+        description_config: DescriptionConfig,
+    ) -> Self {
+        let summary = entry.summary.map(|summary| RawText {
+            html: summary.content_type.subtype() == "html",
+            body: summary.content,
+        });
+
+        let content = entry.content.and_then(|content| {
+            let html = content.content_type.subtype() == "html";
+            content.body.map(|body| RawText { body, html })
+        });
+
+        let description = describe(
+            summary.as_ref(),
+            content.as_ref(),
+            &description_config,
+            Markup::Plain,
+            None,
+        );
+
+        let mut enclosures: Vec<Enclosure> = entry
+            .media
+            .iter()
+            .flat_map(|media| media.content.iter())
+            .filter_map(|content| {
+                content.url.as_ref().map(|url| Enclosure {
+                    url: url.to_string(),
+                    media_type: content.content_type.as_ref().map(|m| m.to_string()),
+                    length: content.size,
+                })
+            })
+            .collect();
+
+        for link in entry.links.iter() {
+            if link.rel.as_deref() == Some("enclosure")
+                && !enclosures.iter().any(|e| e.url == link.href)
+            {
+                enclosures.push(Enclosure {
+                    url: link.href.clone(),
+                    media_type: link.media_type.clone(),
+                    length: link.length,
+                });
             }
-        } else {
-            "".to_owned()
-        };
+        }
 
-        let description = if description.len() > DESCRIPTION_MAX_LENGTH {
-            format!(
-                "{}...",
-                description
-                    .chars()
-                    .take(DESCRIPTION_MAX_LENGTH)
-                    .collect::<String>()
-            )
-        } else {
-            description
-        };
+        let image = entry
+            .media
+            .iter()
+            .flat_map(|media| media.thumbnails.iter())
+            .map(|thumbnail| thumbnail.image.uri.clone())
+            .next()
+            .or_else(|| {
+                enclosures
+                    .iter()
+                    .find(|e| {
+                        e.media_type
+                            .as_ref()
+                            .is_some_and(|m| m.starts_with("image/"))
+                    })
+                    .map(|e| e.url.clone())
+            });
 
         Self {
+            id: entry.id,
             title: entry
                 .title
                 .map(|t| t.content)
@@ -102,6 +546,87 @@ impl From<feed_rs::model::Entry> for Entry {
                 .map(|l| l.href.clone())
                 .unwrap_or_default(),
             timestamp: entry.published.unwrap(),
+            updated: entry.updated,
+            categories: entry
+                .categories
+                .into_iter()
+                .map(|c| c.label.unwrap_or(c.term))
+                .collect(),
+            links: entry.links.into_iter().map(|l| l.href).collect(),
+            enclosures,
+            image,
+            feed_title: feed.title.as_ref().map(|t| t.content.clone()),
+            feed_icon: feed
+                .icon
+                .as_ref()
+                .or(feed.logo.as_ref())
+                .map(|i| i.uri.clone()),
+            tags: tags.to_vec(),
+            fields: Vec::new(),
+            summary,
+            content,
+            description_config,
+            overflow: false,
+        }
+    }
+
+    /// Stands for the `count` entries of a feed left out of a push.
+    pub fn overflow(
+        count: usize,
+        feed: Option<&Feed>,
+        name: &str,
+        url: &str,
+        tags: &[String],
+    ) -> Self {
+        let feed_title = feed
+            .and_then(|feed| feed.title.as_ref())
+            .map(|t| t.content.clone());
+
+        Self {
+            id: format!("{}#overflow", url),
+            title: format!(
+                "…and {} more from {}",
+                count,
+                feed_title.as_deref().unwrap_or(name)
+            ),
+            description: "".to_owned(),
+            author: None,
+            url: feed
+                .into_iter()
+                .flat_map(|feed| feed.links.iter())
+                .find(|l| l.rel.as_deref() != Some("self"))
+                .map_or_else(|| url.to_owned(), |l| l.href.clone()),
+            timestamp: Utc::now(),
+            updated: None,
+            categories: Vec::new(),
+            links: Vec::new(),
+            enclosures: Vec::new(),
+            image: None,
+            feed_title,
+            feed_icon: feed
+                .and_then(|feed| feed.icon.as_ref().or(feed.logo.as_ref()))
+                .map(|i| i.uri.clone()),
+            tags: tags.to_vec(),
+            fields: Vec::new(),
+            summary: None,
+            content: None,
+            description_config: DescriptionConfig::default(),
+            overflow: true,
+        }
+    }
+
+    /// Builds the description again in the output's markup, with the output's
+    /// settings taking precedence.
+    fn describe(&self, config: DescriptionConfig, markup: Markup, limit: Option<usize>) -> Self {
+        Self {
+            description: describe(
+                self.summary.as_ref(),
+                self.content.as_ref(),
+                &config.or(self.description_config),
+                markup,
+                limit,
+            ),
+            ..self.clone()
         }
     }
 }
@@ -0,0 +1,112 @@
+use std::{path::Path, time::Duration};
+
+use chrono::{DateTime, Datelike, Days, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use tokio::sync::Mutex;
+
+use super::buffer::Buffer;
+use crate::{
+    config::{OutsideWindow, ScheduleConfig},
+    error::Error,
+    Result,
+};
+
+/// Delivery windows of an output, entries pushed outside of them are held or
+/// dropped.
+pub struct Schedule {
+    windows: Vec<Window>,
+    timezone: Tz,
+    pub outside: OutsideWindow,
+
+    /// entries waiting for the next window
+    pub held: Buffer,
+    /// held while the held entries are pushed, the inputs and the output's
+    /// task release them concurrently
+    pub releasing: Mutex<()>,
+}
+
+struct Window {
+    days: Vec<Weekday>,
+    from: NaiveTime,
+    /// the window ends the next day when `to` isn't after `from`
+    to: NaiveTime,
+}
+
+impl Schedule {
+    pub async fn new(config: ScheduleConfig, id: &str, state_dir: &Path) -> Result<Self> {
+        if config.windows.is_empty() {
+            return Err(Error::Config(
+                "schedule: at least one window has to be set".to_owned(),
+            ));
+        }
+
+        let windows = config
+            .windows
+            .into_iter()
+            .map(|window| {
+                if window.days.is_empty() {
+                    return Err(Error::Config("schedule: window without days".to_owned()));
+                }
+
+                Ok(Window {
+                    days: window.days,
+                    from: parse_time(&window.from)?,
+                    to: parse_time(&window.to)?,
+                })
+            })
+            .collect::<Result<Vec<Window>>>()?;
+
+        Ok(Self {
+            windows,
+            timezone: config.timezone,
+            outside: config.outside,
+            held: Buffer::load("held", id, state_dir).await?,
+            releasing: Mutex::new(()),
+        })
+    }
+
+    pub fn is_open(&self) -> bool {
+        let now = Utc::now().with_timezone(&self.timezone);
+        self.windows.iter().any(|window| window.contains(now))
+    }
+
+    /// Time left until a window opens, every window has at least a day a week.
+    pub fn until_open(&self) -> Duration {
+        let now = Utc::now().with_timezone(&self.timezone);
+
+        (0..=7)
+            .filter_map(|days| now.date_naive().checked_add_days(Days::new(days)))
+            .flat_map(|day| {
+                self.windows
+                    .iter()
+                    .filter(move |window| window.days.contains(&day.weekday()))
+                    .filter_map(move |window| {
+                        self.timezone
+                            .from_local_datetime(&day.and_time(window.from))
+                            .earliest()
+                    })
+            })
+            .filter(|start| *start > now)
+            .min()
+            .and_then(|start| (start - now).to_std().ok())
+            .unwrap_or_default()
+    }
+}
+
+impl Window {
+    fn contains(&self, time: DateTime<Tz>) -> bool {
+        let (day, time) = (time.weekday(), time.time());
+
+        if self.from < self.to {
+            self.days.contains(&day) && self.from <= time && time < self.to
+        } else {
+            (self.days.contains(&day) && time >= self.from)
+                || (self.days.contains(&day.pred()) && time < self.to)
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|_| Error::Config(format!("schedule: invalid time, expected HH:MM: {}", time)))
+}
@@ -1,44 +1,255 @@
-use log::debug;
-use reqwest::Client;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::format::{Item, StrftimeItems};
+use chrono_tz::Tz;
+use log::{debug, info, warn};
+use reqwest::{header::RETRY_AFTER, Client, RequestBuilder, StatusCode};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use slack_bk::{
-    blocks::{Block, Context, ContextElement, Divider, Header, Section},
+    blocks::{Block, Context, ContextElement, Divider, Header, Image as ImageBlock, Section},
     composition::{MarkdownText, PlainText, Text},
-    elements::{Button, Element},
+    elements::{Button, Element, Image},
 };
 
-use super::{Entry, OutputTrait};
-use crate::Result;
+use super::{batches, description, rate_limit::RateLimiter, truncate, Entry, OutputTrait};
+use crate::{
+    config::{Markup, SlackConfig, SlackLayout},
+    error::Error,
+    Result,
+};
 use async_trait::async_trait;
 
+const API_URL: &str = "https://slack.com/api";
+const RATE_LIMIT_RETRIES: usize = 5;
+
+// https://api.slack.com/reference/block-kit
+const MAX_BLOCKS: usize = 50;
+const MAX_HEADER_LENGTH: usize = 150;
+pub const MAX_TEXT_LENGTH: usize = 3000;
+const MAX_BUTTON_TEXT_LENGTH: usize = 75;
+const MAX_BUTTON_URL_LENGTH: usize = 3000;
+const MAX_MESSAGE_TEXT_LENGTH: usize = 40000;
+const MAX_ALT_TEXT_LENGTH: usize = 2000;
+const MAX_FIELDS: usize = 10;
+const MAX_FIELD_LENGTH: usize = 2000;
+
+enum Destination {
+    Webhook { url: String },
+    Api { token: String, channel: String },
+}
+
 pub struct Slack {
-    url: String,
+    destination: Destination,
     client: Client,
+    rate_limiter: Option<Arc<RateLimiter>>,
+
+    thread_per_input: bool,
+    unfurl_links: Option<bool>,
+    unfurl_media: Option<bool>,
+
+    /// `ts` of the thread parent message for every input
+    threads: Mutex<HashMap<String, String>>,
+
+    layout: Layout,
+}
+
+struct Layout {
+    kind: SlackLayout,
+    timestamp_format: String,
+    timezone: Tz,
+    button_text: String,
+    show_url: bool,
+    show_input_name: bool,
+    show_feed_image: bool,
+    show_images: bool,
 }
 
 impl Slack {
-    pub fn new(url: String, client: Client) -> Self {
-        Self { url, client }
+    pub async fn new(
+        config: SlackConfig,
+        client: Client,
+        rate_limiter: Option<Arc<RateLimiter>>,
+    ) -> Result<Self> {
+        let destination = match (config.url, config.token, config.channel) {
+            (Some(url), None, None) => Destination::Webhook { url },
+            (None, Some(token), Some(channel)) => Destination::Api { token, channel },
+            _ => {
+                return Err(Error::Config(
+                    "slack: either url or token and channel have to be set".to_owned(),
+                ))
+            }
+        };
+
+        if config.thread_per_input && matches!(destination, Destination::Webhook { .. }) {
+            return Err(Error::Config(
+                "slack: thread_per_input requires token and channel".to_owned(),
+            ));
+        }
+
+        // chrono panics when formatting with an invalid format string
+        if StrftimeItems::new(&config.timestamp_format).any(|i| matches!(i, Item::Error)) {
+            return Err(Error::Config(format!(
+                "slack: invalid timestamp_format: {}",
+                config.timestamp_format
+            )));
+        }
+
+        let slack = Self {
+            destination,
+            client,
+            rate_limiter,
+            thread_per_input: config.thread_per_input,
+            unfurl_links: config.unfurl_links,
+            unfurl_media: config.unfurl_media,
+            threads: Mutex::new(HashMap::new()),
+            layout: Layout {
+                kind: config.layout,
+                timestamp_format: config.timestamp_format,
+                timezone: config.timezone,
+                button_text: config.button_text,
+                show_url: config.show_url,
+                show_input_name: config.show_input_name,
+                show_feed_image: config.show_feed_image,
+                show_images: config.show_images,
+            },
+        };
+
+        // auth.test checks the token before the first push and names the bot
+        // and its workspace for the log
+        if let Destination::Api { token, .. } = &slack.destination {
+            let res: ApiResponse = slack
+                .send(|| {
+                    slack
+                        .client
+                        .post(format!("{}/auth.test", API_URL))
+                        .bearer_auth(token)
+                })
+                .await?;
+            let res = res.into_result()?;
+
+            info!(
+                "slack bot \"{}\" will push entries to {}",
+                res.user.unwrap_or_default(),
+                res.team.unwrap_or_default()
+            );
+        }
+
+        Ok(slack)
+    }
+
+    async fn post(&self, message: &Message<'_>) -> Result<Option<String>> {
+        match &self.destination {
+            Destination::Webhook { url } => {
+                self.send_raw(|| self.client.post(url).json(message))
+                    .await?
+                    .error_for_status()?;
+
+                Ok(None)
+            }
+            Destination::Api { token, .. } => {
+                let res: ApiResponse = self
+                    .send(|| {
+                        self.client
+                            .post(format!("{}/chat.postMessage", API_URL))
+                            .bearer_auth(token)
+                            .json(message)
+                    })
+                    .await?;
+
+                Ok(res.into_result()?.ts)
+            }
+        }
+    }
+
+    async fn send<T: for<'de> Deserialize<'de>>(
+        &self,
+        req: impl Fn() -> RequestBuilder,
+    ) -> Result<T> {
+        let res = self.send_raw(req).await?;
+
+        Ok(res.error_for_status()?.json().await?)
+    }
+
+    /// Sends the request, waiting out `Retry-After` whenever slack rate limits it.
+    async fn send_raw(&self, req: impl Fn() -> RequestBuilder) -> Result<reqwest::Response> {
+        let mut retries = 0;
+
+        loop {
+            if let Some(rate_limiter) = self.rate_limiter.as_ref() {
+                rate_limiter.acquire().await;
+            }
+
+            let res = req().send().await?;
+
+            if res.status() != StatusCode::TOO_MANY_REQUESTS || retries == RATE_LIMIT_RETRIES {
+                return Ok(res);
+            }
+
+            let wait = res
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok())
+                .map_or(Duration::from_secs(1), Duration::from_secs);
+
+            warn!("slack rate limited, retrying in {:?}", wait);
+
+            tokio::time::sleep(wait).await;
+            retries += 1;
+        }
     }
 }
 
 #[async_trait]
 impl OutputTrait for Slack {
-    async fn push(&self, _: &str, entries: &[Entry]) -> Result<()> {
+    async fn push(&self, name: &str, entries: &[Entry]) -> Result<()> {
         debug!("pushing {} entries to slack", entries.len());
 
-        for chunk in entries.chunks(10) {
-            let blocks: Vec<Block> = chunk.iter().flat_map(block_from_entry).collect();
+        let channel = match &self.destination {
+            Destination::Api { channel, .. } => Some(channel.as_str()),
+            Destination::Webhook { .. } => None,
+        };
+
+        let entry_blocks = entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry, blocks_from_entry(entry, name, i, &self.layout)));
+
+        for batch in batches(entry_blocks, usize::MAX, MAX_BLOCKS, |(_, b)| b.len()) {
+            // shown in notifications and clients that can't render blocks
+            let text = batch
+                .iter()
+                .map(|(entry, _)| entry.title.as_str())
+                .collect::<Vec<&str>>()
+                .join("\n");
+
+            let thread_ts = if self.thread_per_input {
+                self.threads.lock().unwrap().get(name).cloned()
+            } else {
+                None
+            };
+
+            let message = Message {
+                channel,
+                text: truncate(&text, MAX_MESSAGE_TEXT_LENGTH),
+                blocks: batch.into_iter().flat_map(|(_, b)| b).collect(),
+                thread_ts: thread_ts.as_deref(),
+                unfurl_links: self.unfurl_links,
+                unfurl_media: self.unfurl_media,
+            };
 
-            let message = Message { blocks };
+            let ts = self.post(&message).await?;
 
-            self.client
-                .post(&self.url)
-                .json(&message)
-                .send()
-                .await?
-                .error_for_status()?;
+            if self.thread_per_input && thread_ts.is_none() {
+                if let Some(ts) = ts {
+                    self.threads.lock().unwrap().insert(name.to_owned(), ts);
+                }
+            }
         }
 
         Ok(())
@@ -46,72 +257,245 @@ impl OutputTrait for Slack {
 }
 
 #[derive(Serialize)]
-struct Message {
+struct Message<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel: Option<&'a str>,
+    text: String,
     blocks: Vec<Block>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_ts: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unfurl_links: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unfurl_media: Option<bool>,
 }
 
-fn block_from_entry(entry: &Entry) -> [Block; 4] {
-    let header = Header {
-        text: Text::PlainText(PlainText {
-            text: entry.title.clone(),
-            emoji: false,
-        }),
-        block_id: None,
-    };
-
-    let description = if entry.description.is_empty() {
-        "no description".to_owned()
-    } else {
-        entry.description.clone()
-    };
+#[derive(Deserialize)]
+struct ApiResponse {
+    ok: bool,
+    error: Option<String>,
+    ts: Option<String>,
+    user: Option<String>,
+    team: Option<String>,
+}
 
-    let section = Section {
-        text: Text::Markdown(MarkdownText {
-            text: description,
-            verbatim: false,
-        })
-        .into(),
-        accessory: Element::Button(Button {
-            text: Text::PlainText(PlainText {
-                text: ":link: Open".to_owned(),
-                emoji: true,
-            }),
-            action_id: "button-action".to_owned(),
-            url: Some(entry.url.clone()),
-            ..Default::default()
+impl ApiResponse {
+    fn into_result(self) -> Result<Self> {
+        if self.ok {
+            Ok(self)
+        } else {
+            Err(Error::Slack(self.error.unwrap_or_default()))
+        }
+    }
+}
+
+fn blocks_from_entry(entry: &Entry, name: &str, index: usize, layout: &Layout) -> Vec<Block> {
+    let context = Block::Context(context_from_entry(entry, name, layout));
+
+    let fields = fields_from_entry(entry).map(Block::Section);
+
+    let image = entry
+        .image
+        .as_ref()
+        .filter(|_| layout.show_images)
+        .map(|url| (url.clone(), alt_text(entry)));
+
+    match layout.kind {
+        SlackLayout::Full => {
+            let header = Header {
+                text: Text::PlainText(PlainText {
+                    text: truncate(&entry.title, MAX_HEADER_LENGTH),
+                    emoji: false,
+                }),
+                block_id: None,
+            };
+
+            let description = if entry.description.is_empty() {
+                "no description".to_owned()
+            } else {
+                truncate(&entry.description, MAX_TEXT_LENGTH)
+            };
+
+            let section = Section {
+                text: Text::Markdown(MarkdownText {
+                    text: description,
+                    verbatim: false,
+                })
+                .into(),
+                accessory: Element::Button(Button {
+                    text: Text::PlainText(PlainText {
+                        text: truncate(&layout.button_text, MAX_BUTTON_TEXT_LENGTH),
+                        emoji: true,
+                    }),
+                    // has to be unique within a message
+                    action_id: format!("open-{}", index),
+                    // a longer url would be rejected, the context element still shows it
+                    url: Some(entry.url.clone()).filter(|url| url.len() <= MAX_BUTTON_URL_LENGTH),
+                    ..Default::default()
+                })
+                .into(),
+                ..Default::default()
+            };
+
+            let mut blocks = vec![Block::Header(header), Block::Section(section)];
+            if let Some((image_url, alt_text)) = image {
+                blocks.push(Block::Image(ImageBlock {
+                    image_url,
+                    alt_text,
+                    title: None,
+                    block_id: None,
+                }));
+            }
+            blocks.extend(fields);
+            blocks.push(context);
+            blocks.push(Block::Divider(Divider::default()));
+            blocks
+        }
+        SlackLayout::Compact => {
+            // the title is cut before it's wrapped in the link, a cut link
+            // would show its markup
+            let link_length = Some(description::link_markup("", &entry.url, Markup::Slack))
+                .filter(|_| !entry.url.is_empty())
+                .map(|link| link.chars().count() + 2)
+                .filter(|&length| length < MAX_TEXT_LENGTH);
+
+            let title = match link_length {
+                Some(length) => description::link_markup(
+                    &escape_truncated(&entry.title, MAX_TEXT_LENGTH - length),
+                    &entry.url,
+                    Markup::Slack,
+                ),
+                None => escape_truncated(&entry.title, MAX_TEXT_LENGTH - 2),
+            };
+
+            let section = Section {
+                text: Text::Markdown(MarkdownText {
+                    text: format!("*{}*", title),
+                    verbatim: false,
+                })
+                .into(),
+                accessory: image.map(|(image_url, alt_text)| {
+                    Element::Image(Image {
+                        image_url,
+                        alt_text,
+                    })
+                }),
+                ..Default::default()
+            };
+
+            let mut blocks = vec![Block::Section(section)];
+            blocks.extend(fields);
+            blocks.push(context);
+            blocks
+        }
+    }
+}
+
+fn fields_from_entry(entry: &Entry) -> Option<Section> {
+    if entry.fields.is_empty() {
+        return None;
+    }
+
+    let fields = entry
+        .fields
+        .iter()
+        .take(MAX_FIELDS)
+        .map(|field| {
+            Text::Markdown(MarkdownText {
+                text: truncate(
+                    &format!("*{}*\n{}", escape(&field.name), field.value),
+                    MAX_FIELD_LENGTH,
+                ),
+                verbatim: false,
+            })
         })
-        .into(),
+        .collect();
+
+    Some(Section {
+        fields,
         ..Default::default()
-    };
+    })
+}
+
+fn context_from_entry(entry: &Entry, name: &str, layout: &Layout) -> Context {
+    let mut ctx_elements = Vec::with_capacity(5);
+
+    if layout.show_feed_image {
+        if let Some(icon) = entry.feed_icon.as_ref() {
+            ctx_elements.push(ContextElement::Image(Element::Image(Image {
+                image_url: icon.clone(),
+                alt_text: name.to_owned(),
+            })));
+        }
+    }
 
-    let mut ctx_elements = Vec::with_capacity(3);
+    if layout.show_input_name {
+        ctx_elements.push(ContextElement::Text(Text::Markdown(MarkdownText {
+            text: format!("*{}*", escape(name)),
+            verbatim: false,
+        })));
+    }
 
     if let Some(author) = entry.author.as_ref() {
         ctx_elements.push(ContextElement::Text(Text::Markdown(MarkdownText {
-            text: author.clone(),
+            text: truncate(author, MAX_TEXT_LENGTH),
             verbatim: false,
         })));
     }
 
-    ctx_elements.push(ContextElement::Text(Text::Markdown(MarkdownText {
-        text: entry.url.clone(),
-        verbatim: false,
-    })));
+    if layout.show_url {
+        ctx_elements.push(ContextElement::Text(Text::Markdown(MarkdownText {
+            text: truncate(&entry.url, MAX_TEXT_LENGTH),
+            verbatim: false,
+        })));
+    }
 
     ctx_elements.push(ContextElement::Text(Text::PlainText(PlainText {
-        text: entry.timestamp.format("%d %b %Y %I:%M %p %Z").to_string(),
+        text: entry
+            .timestamp
+            .with_timezone(&layout.timezone)
+            .format(&layout.timestamp_format)
+            .to_string(),
         emoji: false,
     })));
 
-    let context = Context {
+    Context {
         elements: ctx_elements,
         ..Default::default()
-    };
-
-    [
-        Block::Header(header),
-        Block::Section(section),
-        Block::Context(context),
-        Block::Divider(Divider::default()),
-    ]
+    }
+}
+
+fn alt_text(entry: &Entry) -> String {
+    if entry.title.is_empty() {
+        "image".to_owned()
+    } else {
+        truncate(&entry.title, MAX_ALT_TEXT_LENGTH)
+    }
+}
+
+fn escape(text: &str) -> String {
+    description::escape(text, Markup::Slack)
+}
+
+/// Escapes `text`, cut so that the escaped text fits into `max` characters.
+fn escape_truncated(text: &str, max: usize) -> String {
+    let escaped = escape(text);
+    if escaped.chars().count() <= max {
+        return escaped;
+    }
+
+    let mut truncated = String::new();
+    let mut length = 1;
+
+    for c in text.chars() {
+        let c = escape(c.encode_utf8(&mut [0; 4]));
+        length += c.chars().count();
+        if length > max {
+            break;
+        }
+        truncated.push_str(&c);
+    }
+
+    truncated.push('…');
+    truncated
 }
@@ -0,0 +1,121 @@
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime,
+};
+use chrono_tz::Tz;
+use handlebars::{handlebars_helper, no_escape, Handlebars};
+use serde_json::{json, Value};
+
+use super::{description, truncate, Entry, Field};
+use crate::{
+    config::{Markup, TemplateConfig},
+    Result,
+};
+
+const TITLE: &str = "title";
+const BODY: &str = "body";
+
+/// Handlebars templates overriding the title, the description and the extra
+/// fields of every entry pushed to an output.
+pub struct Template {
+    registry: Handlebars<'static>,
+    fields: Vec<(String, bool)>,
+}
+
+impl Template {
+    pub async fn new(config: TemplateConfig) -> Result<Self> {
+        let mut registry = Handlebars::new();
+
+        // outputs aren't html, escaping is left to the `escape_markdown` helper
+        registry.register_escape_fn(no_escape);
+
+        registry.register_helper("truncate", Box::new(truncate_helper));
+        registry.register_helper("date", Box::new(date_helper));
+        registry.register_helper("strip_html", Box::new(strip_html_helper));
+        registry.register_helper("escape_markdown", Box::new(escape_markdown_helper));
+        registry.register_helper("join", Box::new(join_helper));
+
+        if let Some(title) = config.title {
+            registry.register_template_string(TITLE, title.load().await?)?;
+        }
+
+        if let Some(body) = config.body {
+            registry.register_template_string(BODY, body.load().await?)?;
+        }
+
+        let mut fields = Vec::with_capacity(config.fields.len());
+
+        for (i, field) in config.fields.into_iter().enumerate() {
+            registry
+                .register_template_string(&field_template_name(i), field.value.load().await?)?;
+            fields.push((field.name, field.inline));
+        }
+
+        Ok(Self { registry, fields })
+    }
+
+    pub fn render(&self, name: &str, entry: &Entry) -> Result<Entry> {
+        let mut data = serde_json::to_value(entry).unwrap();
+        data["input"] = json!(name);
+
+        let mut entry = entry.clone();
+
+        if self.registry.has_template(TITLE) {
+            entry.title = self.registry.render(TITLE, &data)?;
+        }
+
+        if self.registry.has_template(BODY) {
+            entry.description = self.registry.render(BODY, &data)?;
+        }
+
+        for (i, (name, inline)) in self.fields.iter().enumerate() {
+            entry.fields.push(Field {
+                name: name.clone(),
+                value: self.registry.render(&field_template_name(i), &data)?,
+                inline: *inline,
+            });
+        }
+
+        Ok(entry)
+    }
+}
+
+fn field_template_name(index: usize) -> String {
+    format!("field-{}", index)
+}
+
+/// Renders a helper argument as text, `null` (e.g. a missing author) is empty.
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => "".to_owned(),
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+handlebars_helper!(truncate_helper: |value: Json, length: u64| {
+    truncate(&text(value), length as usize)
+});
+
+handlebars_helper!(date_helper: |value: Json, format: str, {timezone: str = "UTC"}| {
+    let value = text(value);
+
+    let valid_format = !StrftimeItems::new(format).any(|i| matches!(i, Item::Error));
+
+    match (DateTime::parse_from_rfc3339(&value), timezone.parse::<Tz>()) {
+        (Ok(date), Ok(tz)) if valid_format => date.with_timezone(&tz).format(format).to_string(),
+        _ => value,
+    }
+});
+
+handlebars_helper!(strip_html_helper: |value: Json| {
+    description::html_to_text(&text(value))
+});
+
+handlebars_helper!(escape_markdown_helper: |value: Json| {
+    description::escape(&text(value), Markup::Markdown)
+});
+
+handlebars_helper!(join_helper: |values: array, {separator: str = ", "}| {
+    values.iter().map(text).collect::<Vec<String>>().join(separator)
+});
@@ -0,0 +1,554 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use feed_rs::model::Feed;
+use hmac::{digest::KeyInit, Hmac, Mac};
+use hyper::{
+    body::{to_bytes, Bytes},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::{debug, info, warn};
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::{Client, Url};
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+use tokio::{
+    sync::{broadcast::Receiver, mpsc},
+    time::Instant,
+};
+
+use crate::{config::WebSubConfig, Result};
+
+/// How long a hub has to verify a subscription before it's requested again.
+const VERIFICATION_TIMEOUT: Duration = Duration::from_secs(60 * 5);
+
+/// Pushed contents waiting for their input.
+const PUSH_QUEUE: usize = 16;
+
+/// Subscribes inputs to the WebSub hubs advertised by their feeds and
+/// receives the content the hubs push.
+pub struct WebSub {
+    config: WebSubConfig,
+    client: Client,
+    /// subscriptions by callback id
+    subscriptions: Mutex<HashMap<String, Subscription>>,
+}
+
+struct Subscription {
+    input: String,
+    hub: Option<String>,
+    topic: Option<String>,
+    secret: String,
+    state: State,
+    /// a renewal of the active subscription was requested
+    renewing: bool,
+    pushes: mpsc::Sender<Bytes>,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Inactive,
+    /// requested, waiting for the hub's verification
+    Pending(Instant),
+    Active {
+        until: Instant,
+        lease: Duration,
+    },
+    /// the hub refused, asked again after a lease period
+    Denied(Instant),
+}
+
+impl WebSub {
+    pub fn new(config: WebSubConfig, client: Client) -> Self {
+        Self {
+            config,
+            client,
+            subscriptions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Registers an input, the returned channel receives the pushed content.
+    pub fn register(&self, input: &str) -> mpsc::Receiver<Bytes> {
+        let (tx, rx) = mpsc::channel(PUSH_QUEUE);
+
+        self.subscriptions.lock().unwrap().insert(
+            callback_id(input),
+            Subscription {
+                input: input.to_owned(),
+                hub: None,
+                topic: None,
+                secret: String::new(),
+                state: State::Inactive,
+                renewing: false,
+                pushes: tx,
+            },
+        );
+
+        rx
+    }
+
+    /// Whether the hub pushes the updates of the input, polling isn't needed.
+    pub fn is_active(&self, input: &str) -> bool {
+        let subscriptions = self.subscriptions.lock().unwrap();
+
+        subscriptions.get(&callback_id(input)).is_some_and(
+            |s| matches!(s.state, State::Active { until, .. } if until > Instant::now()),
+        )
+    }
+
+    /// Subscribes to the hub advertised by the feed unless a subscription is
+    /// running already. Failures are logged, the input keeps polling.
+    pub async fn subscribe(&self, input: &str, url: &str, feed: &Feed) {
+        let hub = feed
+            .links
+            .iter()
+            .find(|l| l.rel.as_deref() == Some("hub"))
+            .map(|l| l.href.clone());
+
+        let hub = match hub {
+            Some(hub) => hub,
+            None => return,
+        };
+
+        let topic = feed
+            .links
+            .iter()
+            .find(|l| l.rel.as_deref() == Some("self"))
+            .map_or_else(|| url.to_owned(), |l| l.href.clone());
+
+        {
+            let mut subscriptions = self.subscriptions.lock().unwrap();
+            let subscription = match subscriptions.get_mut(&callback_id(input)) {
+                Some(subscription) => subscription,
+                None => return,
+            };
+
+            let now = Instant::now();
+            let running = match subscription.state {
+                State::Inactive => false,
+                State::Pending(since) => now.duration_since(since) < VERIFICATION_TIMEOUT,
+                State::Active { until, .. } => until > now,
+                State::Denied(since) => now.duration_since(since) < self.config.lease,
+            };
+
+            if running {
+                return;
+            }
+
+            subscription.hub = Some(hub);
+            subscription.topic = Some(topic);
+        }
+
+        self.request(input).await;
+    }
+
+    /// Renews the subscription of the input when its lease is running out.
+    pub async fn renew(&self, input: &str) {
+        let expiring = {
+            let subscriptions = self.subscriptions.lock().unwrap();
+
+            subscriptions.get(&callback_id(input)).is_some_and(|s| {
+                matches!(s.state, State::Active { until, lease }
+                    if until.saturating_duration_since(Instant::now()) < lease / 10)
+            })
+        };
+
+        if expiring {
+            self.request(input).await;
+        }
+    }
+
+    /// Sends a subscription request, renewals keep the secret so content
+    /// signed before their verification is still accepted.
+    async fn request(&self, input: &str) {
+        let id = callback_id(input);
+
+        let (hub, topic, secret) = {
+            let mut subscriptions = self.subscriptions.lock().unwrap();
+            let subscription = match subscriptions.get_mut(&id) {
+                Some(subscription) => subscription,
+                None => return,
+            };
+
+            if subscription.secret.is_empty() {
+                subscription.secret = rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(32)
+                    .map(char::from)
+                    .collect();
+            }
+
+            match subscription.state {
+                State::Active { .. } => subscription.renewing = true,
+                _ => subscription.state = State::Pending(Instant::now()),
+            }
+
+            match (subscription.hub.clone(), subscription.topic.clone()) {
+                (Some(hub), Some(topic)) => (hub, topic, subscription.secret.clone()),
+                _ => return,
+            }
+        };
+
+        let callback = format!("{}/{}", self.config.callback_url.trim_end_matches('/'), id);
+        let lease = self.config.lease.as_secs().to_string();
+
+        let res = self
+            .client
+            .post(&hub)
+            .form(&[
+                ("hub.mode", "subscribe"),
+                ("hub.callback", &callback),
+                ("hub.topic", &topic),
+                ("hub.lease_seconds", &lease),
+                ("hub.secret", &secret),
+            ])
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+
+        match res {
+            Ok(_) => info!("subscribed \"{}\" to websub hub {}", input, hub),
+            Err(e) => {
+                warn!(
+                    "websub subscription of \"{}\" failed, polling instead: {}",
+                    input, e
+                );
+
+                if let Some(subscription) = self.subscriptions.lock().unwrap().get_mut(&id) {
+                    if matches!(subscription.state, State::Pending(_)) {
+                        subscription.state = State::Inactive;
+                    }
+                    subscription.renewing = false;
+                }
+            }
+        }
+    }
+
+    /// Runs the callback endpoint.
+    pub async fn serve(self: Arc<Self>, mut kill: Receiver<()>) -> Result<()> {
+        let websub = self.clone();
+
+        let make_service = make_service_fn(move |_| {
+            let websub = websub.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let websub = websub.clone();
+                    async move { Ok::<_, Infallible>(websub.handle(req).await) }
+                }))
+            }
+        });
+
+        info!("websub callback listening on {}", self.config.listen);
+
+        Server::try_bind(&self.config.listen)?
+            .serve(make_service)
+            .with_graceful_shutdown(async move {
+                kill.recv().await.ok();
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        // the public callback url may have a different path prefix
+        let id = req
+            .uri()
+            .path()
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+
+        match *req.method() {
+            Method::GET => self.verify(&id, req.uri().query().unwrap_or_default()),
+            Method::POST => {
+                let signature = req
+                    .headers()
+                    .get("x-hub-signature")
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_owned);
+
+                match to_bytes(req.into_body()).await {
+                    Ok(body) => self.receive(&id, signature.as_deref(), body).await,
+                    Err(_) => status(StatusCode::BAD_REQUEST),
+                }
+            }
+            _ => status(StatusCode::METHOD_NOT_ALLOWED),
+        }
+    }
+
+    /// Answers the hub's verification of a subscription.
+    fn verify(&self, id: &str, query: &str) -> Response<Body> {
+        let params: HashMap<String, String> = Url::parse(&format!("http://callback/?{}", query))
+            .map(|url| url.query_pairs().into_owned().collect())
+            .unwrap_or_default();
+        let param = |name: &str| params.get(name).map(String::as_str);
+
+        // a lease longer than requested would delay the renewal past a hub
+        // that forgets the subscription, and could overflow `until`
+        let lease = param("hub.lease_seconds")
+            .and_then(|lease| lease.parse().ok())
+            .map_or(self.config.lease, Duration::from_secs)
+            .min(self.config.lease);
+        let until = Instant::now().checked_add(lease);
+
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let subscription = match subscriptions.get_mut(id) {
+            Some(subscription) => subscription,
+            None => return status(StatusCode::NOT_FOUND),
+        };
+
+        if param("hub.topic") != subscription.topic.as_deref() {
+            return status(StatusCode::NOT_FOUND);
+        }
+
+        match (param("hub.mode"), param("hub.challenge"), until) {
+            // only a requested subscription or the renewal of an active one
+            // is verified
+            (Some("subscribe"), Some(challenge), Some(until))
+                if matches!(subscription.state, State::Pending(_))
+                    || matches!(subscription.state, State::Active { .. })
+                        && subscription.renewing =>
+            {
+                info!(
+                    "websub subscription of \"{}\" verified for {:?}",
+                    subscription.input, lease
+                );
+                subscription.state = State::Active { until, lease };
+                subscription.renewing = false;
+
+                Response::new(Body::from(challenge.to_owned()))
+            }
+            (Some("denied"), ..) => {
+                warn!(
+                    "websub subscription of \"{}\" denied: {}",
+                    subscription.input,
+                    param("hub.reason").unwrap_or_default()
+                );
+                subscription.state = State::Denied(Instant::now());
+                subscription.renewing = false;
+
+                status(StatusCode::OK)
+            }
+            _ => status(StatusCode::NOT_FOUND),
+        }
+    }
+
+    /// Passes pushed content to its input.
+    async fn receive(&self, id: &str, signature: Option<&str>, body: Bytes) -> Response<Body> {
+        let (pushes, input) = {
+            let subscriptions = self.subscriptions.lock().unwrap();
+            let subscription = match subscriptions.get(id) {
+                Some(subscription) => subscription,
+                None => return status(StatusCode::NOT_FOUND),
+            };
+
+            // unsigned or wrongly signed content is acknowledged and ignored
+            if !signature.is_some_and(|s| is_signed(&subscription.secret, s, &body)) {
+                warn!(
+                    "ignoring websub content of \"{}\" with an invalid signature",
+                    subscription.input
+                );
+                return status(StatusCode::ACCEPTED);
+            }
+
+            (subscription.pushes.clone(), subscription.input.clone())
+        };
+
+        debug!("received websub content for \"{}\"", input);
+
+        if pushes.send(body).await.is_err() {
+            return status(StatusCode::GONE);
+        }
+
+        status(StatusCode::ACCEPTED)
+    }
+}
+
+/// Path segment of the callback url of an input.
+fn callback_id(input: &str) -> String {
+    hex::encode(input)
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = status;
+    res
+}
+
+/// Checks a `X-Hub-Signature` header, e.g. `sha256=<hex>`.
+fn is_signed(secret: &str, signature: &str, body: &[u8]) -> bool {
+    let (algorithm, signature) = match signature.split_once('=') {
+        Some(split) => split,
+        None => return false,
+    };
+
+    let signature = match hex::decode(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+
+    match algorithm {
+        "sha1" => check::<Hmac<Sha1>>(secret, body, &signature),
+        "sha256" => check::<Hmac<Sha256>>(secret, body, &signature),
+        "sha384" => check::<Hmac<Sha384>>(secret, body, &signature),
+        "sha512" => check::<Hmac<Sha512>>(secret, body, &signature),
+        _ => false,
+    }
+}
+
+fn check<M: Mac + KeyInit>(secret: &str, body: &[u8], signature: &[u8]) -> bool {
+    let mut mac = <M as KeyInit>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body);
+    mac.verify_slice(signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOPIC: &str = "https://example.com/feed.xml";
+
+    fn websub() -> WebSub {
+        let websub = WebSub::new(
+            WebSubConfig {
+                listen: "127.0.0.1:0".parse().unwrap(),
+                callback_url: "https://example.com/websub".to_owned(),
+                lease: Duration::from_secs(60 * 60),
+            },
+            Client::new(),
+        );
+        websub.register("news");
+
+        websub
+    }
+
+    fn set_state(websub: &WebSub, state: State, renewing: bool) {
+        let mut subscriptions = websub.subscriptions.lock().unwrap();
+        let subscription = subscriptions.get_mut(&callback_id("news")).unwrap();
+        subscription.topic = Some(TOPIC.to_owned());
+        subscription.state = state;
+        subscription.renewing = renewing;
+    }
+
+    fn state(websub: &WebSub) -> State {
+        websub.subscriptions.lock().unwrap()[&callback_id("news")].state
+    }
+
+    fn verify(websub: &WebSub, query: &str) -> Response<Body> {
+        websub.verify(
+            &callback_id("news"),
+            &format!("hub.topic={}&{}", TOPIC, query),
+        )
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = <Hmac<Sha256> as KeyInit>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    #[test]
+    fn checks_signatures() {
+        let signature = sign("secret", b"body");
+
+        assert!(is_signed("secret", &signature, b"body"));
+        assert!(!is_signed("other", &signature, b"body"));
+        assert!(!is_signed("secret", &signature, b"other"));
+        assert!(!is_signed(
+            "secret",
+            &signature.replace("sha256", "md5"),
+            b"body"
+        ));
+        assert!(!is_signed("secret", "sha256=zz", b"body"));
+        assert!(!is_signed("secret", "sha256", b"body"));
+    }
+
+    #[tokio::test]
+    async fn verifies_a_requested_subscription() {
+        let websub = websub();
+        set_state(&websub, State::Pending(Instant::now()), false);
+
+        let res = verify(
+            &websub,
+            "hub.mode=subscribe&hub.challenge=abc&hub.lease_seconds=60",
+        );
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(to_bytes(res.into_body()).await.unwrap(), "abc");
+        assert!(matches!(
+            state(&websub),
+            State::Active { lease, .. } if lease == Duration::from_secs(60)
+        ));
+    }
+
+    #[test]
+    fn caps_the_granted_lease() {
+        let websub = websub();
+        set_state(&websub, State::Pending(Instant::now()), false);
+
+        let res = verify(
+            &websub,
+            "hub.mode=subscribe&hub.challenge=abc&hub.lease_seconds=18446744073709551615",
+        );
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(matches!(
+            state(&websub),
+            State::Active { lease, .. } if lease == Duration::from_secs(60 * 60)
+        ));
+    }
+
+    #[test]
+    fn refuses_unrequested_verifications() {
+        let websub = websub();
+        let query = "hub.mode=subscribe&hub.challenge=abc";
+
+        set_state(&websub, State::Inactive, false);
+        assert_eq!(verify(&websub, query).status(), StatusCode::NOT_FOUND);
+
+        let until = Instant::now() + Duration::from_secs(60);
+        let lease = Duration::from_secs(60);
+        set_state(&websub, State::Active { until, lease }, false);
+        assert_eq!(verify(&websub, query).status(), StatusCode::NOT_FOUND);
+
+        set_state(&websub, State::Active { until, lease }, true);
+        assert_eq!(verify(&websub, query).status(), StatusCode::OK);
+
+        set_state(&websub, State::Pending(Instant::now()), false);
+        let res = websub.verify(&callback_id("news"), query);
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = websub.verify("unknown", query);
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn backs_off_after_a_denial() {
+        let websub = websub();
+        set_state(&websub, State::Pending(Instant::now()), false);
+
+        let res = verify(&websub, "hub.mode=denied&hub.reason=nope");
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(matches!(state(&websub), State::Denied(_)));
+
+        let feed = feed_rs::parser::parse(
+            format!(
+                r#"<feed xmlns="http://www.w3.org/2005/Atom"><title>t</title>
+                <link rel="hub" href="http://127.0.0.1:9/"/>
+                <link rel="self" href="{}"/></feed>"#,
+                TOPIC
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        websub.subscribe("news", TOPIC, &feed).await;
+
+        assert!(matches!(state(&websub), State::Denied(_)));
+    }
+}